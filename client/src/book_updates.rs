@@ -0,0 +1,101 @@
+use std::cmp::Ordering;
+
+use crate::orderbook::{BookUpdate, Level, LevelAction, LevelUpdate, Summary};
+
+/// Aggregated orderbook maintained from book updates
+#[derive(Default)]
+pub struct LocalBook {
+    bids: Vec<Level>,
+    asks: Vec<Level>,
+    last_sequence: u64,
+}
+
+impl LocalBook {
+    /// Fails on sequence gap, then a new snapshot is needed
+    pub fn apply(&mut self, update: BookUpdate) -> Result<(), String> {
+        if update.sequence != self.last_sequence + 1 {
+            return Err(format!(
+                "Sequence gap: expected {}, got {}",
+                self.last_sequence + 1,
+                update.sequence
+            ));
+        }
+        self.last_sequence = update.sequence;
+
+        if update.snapshot {
+            self.bids.clear();
+            self.asks.clear();
+        }
+
+        apply_level_updates(&mut self.bids, update.bids);
+        apply_level_updates(&mut self.asks, update.asks);
+
+        // Same order as in summaries: best price first (after fees, if levels have effective prices), then bigger amount
+        self.bids.sort_by(|a, b| {
+            ranking_price(b)
+                .partial_cmp(&ranking_price(a))
+                .unwrap_or(Ordering::Equal)
+                .then(b.amount.partial_cmp(&a.amount).unwrap_or(Ordering::Equal))
+        });
+        self.asks.sort_by(|a, b| {
+            ranking_price(a)
+                .partial_cmp(&ranking_price(b))
+                .unwrap_or(Ordering::Equal)
+                .then(b.amount.partial_cmp(&a.amount).unwrap_or(Ordering::Equal))
+        });
+
+        Ok(())
+    }
+
+    /// Spread is calculated from double prices, so it has no exact value
+    pub fn to_summary(&self) -> Summary {
+        let (spread, crossed) = match (self.asks.first(), self.bids.first()) {
+            (Some(ask), Some(bid)) => (Some(ask.price - bid.price), bid.price >= ask.price),
+            _ => (None, false),
+        };
+        let effective_spread = match (self.asks.first(), self.bids.first()) {
+            (Some(ask), Some(bid)) => ask
+                .effective_price
+                .zip(bid.effective_price)
+                .map(|(ask_price, bid_price)| ask_price - bid_price),
+            _ => None,
+        };
+
+        Summary {
+            spread,
+            effective_spread,
+            crossed,
+            bids: self.bids.clone(),
+            asks: self.asks.clone(),
+            ..Default::default()
+        }
+    }
+}
+
+fn ranking_price(level: &Level) -> f64 {
+    level.effective_price.unwrap_or(level.price)
+}
+
+fn apply_level_updates(levels: &mut Vec<Level>, updates: Vec<LevelUpdate>) {
+    for update in updates {
+        let level = match update.level {
+            Some(level) => level,
+            None => continue,
+        };
+
+        let position = levels.iter().position(|existing| {
+            existing.exchange == level.exchange && existing.exact_price == level.exact_price
+        });
+
+        match (LevelAction::from_i32(update.action), position) {
+            (Some(LevelAction::Delete), Some(position)) => {
+                levels.remove(position);
+            }
+            (Some(LevelAction::Insert | LevelAction::Update), Some(position)) => {
+                levels[position] = level;
+            }
+            (Some(LevelAction::Insert | LevelAction::Update), None) => levels.push(level),
+            _ => {}
+        }
+    }
+}
@@ -1,9 +1,9 @@
-use std::error::Error;
+use std::{env, error::Error};
 
 use tonic::{transport::Channel, Request};
 
 use orderbook::orderbook_aggregator_client::OrderbookAggregatorClient;
-use orderbook::Empty;
+use orderbook::{QuoteRequest, Side, SummaryRequest};
 
 pub mod orderbook {
     tonic::include_proto!("orderbook"); // The string specified here must match the proto package name
@@ -12,11 +12,18 @@ pub mod orderbook {
 mod print_summary_table;
 use print_summary_table::print_summary_as_table;
 
+mod book_updates;
+use book_updates::LocalBook;
+
+mod print_quote_table;
+use print_quote_table::print_quote_as_table;
+
 async fn print_summaries(
     client: &mut OrderbookAggregatorClient<Channel>,
+    summary_request: SummaryRequest,
 ) -> Result<(), Box<dyn Error>> {
     let mut stream = client
-        .book_summary(Request::new(Empty {}))
+        .book_summary(Request::new(summary_request))
         .await?
         .into_inner();
 
@@ -36,13 +43,132 @@ async fn print_summaries(
     Ok(())
 }
 
+/// Maintains the orderbook from book updates and prints it on every update.
+/// On sequence gap, opens a new stream to get a new snapshot.
+async fn print_book_updates(
+    client: &mut OrderbookAggregatorClient<Channel>,
+    summary_request: SummaryRequest,
+) -> Result<(), Box<dyn Error>> {
+    loop {
+        let mut stream = client
+            .book_updates(Request::new(summary_request.clone()))
+            .await?
+            .into_inner();
+        let mut local_book = LocalBook::default();
+
+        loop {
+            let update = match stream.message().await? {
+                Some(update) => update,
+                None => return Ok(()),
+            };
+
+            if let Err(error) = local_book.apply(update) {
+                eprintln!("{}. Requesting a new snapshot...", error);
+                break;
+            }
+
+            print_summary_as_table(local_book.to_summary());
+        }
+    }
+}
+
+/// Prints the latest summary once, instead of streaming updates
+async fn print_summary(
+    client: &mut OrderbookAggregatorClient<Channel>,
+    summary_request: SummaryRequest,
+) -> Result<(), Box<dyn Error>> {
+    let summary = client
+        .get_summary(Request::new(summary_request))
+        .await?
+        .into_inner();
+
+    print_summary_as_table(summary);
+
+    Ok(())
+}
+
+/// Prints how a market order would be executed across exchanges
+async fn print_quote(
+    client: &mut OrderbookAggregatorClient<Channel>,
+    quote_request: QuoteRequest,
+) -> Result<(), Box<dyn Error>> {
+    let quote = client
+        .quote_execution(Request::new(quote_request))
+        .await?
+        .into_inner();
+
+    print_quote_as_table(quote);
+
+    Ok(())
+}
+
+/// Whether env var is `true`
+fn get_env_flag(var_name: &str) -> bool {
+    env::var(var_name)
+        .map(|value| value == "true")
+        .unwrap_or_default()
+}
+
+/// Comma-separated list from env var, like `binance,kraken`
+fn get_env_list(var_name: &str) -> Vec<String> {
+    env::var(var_name)
+        .unwrap_or_default()
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let port = env!("PORT");
     let url = format!("http://[::1]:{}", port);
+    // Symbol can be changed at runtime to watch another pair served by the server
+    let symbol = env::var("SYMBOL").unwrap_or_else(|_| env!("SYMBOL").to_string());
+    // 0 means the server depth
+    let depth = env::var("DEPTH")
+        .ok()
+        .and_then(|depth| depth.parse().ok())
+        .unwrap_or_default();
+
+    let summary_request = SummaryRequest {
+        symbol,
+        depth,
+        include_exchanges: get_env_list("EXCHANGES"),
+        exclude_exchanges: get_env_list("EXCLUDE_EXCHANGES"),
+        merge_levels: get_env_flag("MERGE_LEVELS"),
+        // Server tick size is used if not set
+        tick_size: env::var("TICK_SIZE").ok(),
+        fee_adjusted: get_env_flag("FEE_ADJUSTED"),
+    };
+
     let mut client = OrderbookAggregatorClient::connect(url).await?;
 
-    print_summaries(&mut client).await?;
+    // Like `1.5`, quotes an order of this quantity instead of printing the orderbook
+    let quote_quantity = env::var("QUOTE_QUANTITY").ok();
+
+    if let Some(quantity) = quote_quantity {
+        let side = match env::var("QUOTE_SIDE").as_deref() {
+            Ok("sell") => Side::Sell,
+            _ => Side::Buy,
+        };
+
+        let quote_request = QuoteRequest {
+            symbol: summary_request.symbol,
+            side: side as i32,
+            quantity,
+            include_exchanges: summary_request.include_exchanges,
+            exclude_exchanges: summary_request.exclude_exchanges,
+            fee_adjusted: summary_request.fee_adjusted,
+        };
+        print_quote(&mut client, quote_request).await?;
+    } else if get_env_flag("ONCE") {
+        print_summary(&mut client, summary_request).await?;
+    } else if get_env_flag("UPDATES") {
+        print_book_updates(&mut client, summary_request).await?;
+    } else {
+        print_summaries(&mut client, summary_request).await?;
+    }
 
     Ok(())
 }
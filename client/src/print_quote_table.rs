@@ -0,0 +1,62 @@
+use comfy_table::modifiers::UTF8_ROUND_CORNERS;
+use comfy_table::presets::UTF8_FULL;
+use comfy_table::{Cell, Table};
+
+use crate::orderbook::Quote;
+
+/// Exact value if it's sent by the server, otherwise the double one
+fn format_number(exact: Option<String>, number: Option<f64>) -> String {
+    exact
+        .or_else(|| number.map(|number| number.to_string()))
+        .unwrap_or_else(|| "-".to_string())
+}
+
+pub fn print_quote_as_table(quote: Quote) {
+    let mut table = Table::new();
+    table
+        .load_preset(UTF8_FULL)
+        .apply_modifier(UTF8_ROUND_CORNERS)
+        .set_header(vec![
+            Cell::new("Exchange"),
+            Cell::new("Quantity"),
+            Cell::new("VWAP"),
+        ]);
+
+    for allocation in quote.allocations {
+        table.add_row(vec![
+            allocation.exchange,
+            allocation.exact_quantity,
+            allocation.exact_vwap,
+        ]);
+    }
+
+    println!("Quote #{}", quote.sequence);
+    println!(
+        "Filled: {}, unfilled: {}",
+        quote.exact_filled_quantity, quote.exact_unfilled_quantity
+    );
+    println!("VWAP: {}", format_number(quote.exact_vwap, quote.vwap));
+    if quote.effective_vwap.is_some() {
+        println!(
+            "VWAP after fees: {}",
+            format_number(quote.exact_effective_vwap, quote.effective_vwap)
+        );
+    }
+    println!(
+        "Worst price: {}",
+        format_number(quote.exact_worst_price, quote.worst_price)
+    );
+    println!(
+        "Mid price: {}",
+        format_number(quote.exact_mid_price, quote.mid_price)
+    );
+    match quote.slippage_bps {
+        Some(slippage_bps) => println!(
+            "Slippage: {} ({:.2} bps)",
+            format_number(quote.exact_slippage, quote.slippage),
+            slippage_bps
+        ),
+        None => println!("Slippage: -"),
+    }
+    println!("{}", table);
+}
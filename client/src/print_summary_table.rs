@@ -1,15 +1,71 @@
-use std::cmp::min;
+use std::cmp::max;
 
 use comfy_table::modifiers::UTF8_ROUND_CORNERS;
 use comfy_table::presets::UTF8_FULL;
 use comfy_table::{Cell, Color, Table};
 
-use crate::orderbook::Summary;
+use crate::orderbook::{ConnectionState, ExchangeStatus, ExclusionReason, Level, Summary};
 
 fn clear_terminal() {
     println!("{}c", 27 as char);
 }
 
+/// Like `binance: live, data age 120 ms, 2 stalls` or `bitstamp: backing off, excluded (no data)`
+fn format_exchange_status(status: &ExchangeStatus) -> String {
+    let state = ConnectionState::from_i32(status.state)
+        .map(|state| state.as_str_name().to_lowercase().replace('_', " "))
+        .unwrap_or_default();
+
+    let mut parts = vec![state];
+    if let Some(data_age_ms) = status.data_age_ms {
+        parts.push(format!("data age {} ms", data_age_ms));
+    }
+    if status.stalls > 0 {
+        parts.push(format!("{} stalls", status.stalls));
+    }
+    if !status.maker_fee.is_empty() && (status.maker_fee != "0" || status.taker_fee != "0") {
+        parts.push(format!(
+            "maker fee {}, taker fee {}",
+            status.maker_fee, status.taker_fee
+        ));
+    }
+    if !status.included {
+        let reason = ExclusionReason::from_i32(status.exclusion_reason)
+            .map(|reason| reason.as_str_name().to_lowercase().replace('_', " "))
+            .unwrap_or_default();
+        parts.push(format!("excluded ({})", reason));
+    }
+
+    format!("{}: {}", status.exchange, parts.join(", "))
+}
+
+/// Exchange name, or amount of every exchange for merged levels, like `binance 1.5, kraken 0.2`
+fn format_level_exchange(level: &Level) -> String {
+    if level.contributions.len() < 2 {
+        return level.exchange.clone();
+    }
+
+    level
+        .contributions
+        .iter()
+        .map(|contribution| format!("{} {}", contribution.exchange, contribution.exact_amount))
+        .collect::<Vec<String>>()
+        .join(", ")
+}
+
+/// Price, and effective price after fees for fee-adjusted summaries, like `0.05 (0.05005)`
+fn format_level_price(level: &Level) -> String {
+    let effective_price = level
+        .exact_effective_price
+        .clone()
+        .or_else(|| level.effective_price.map(|price| price.to_string()));
+
+    match effective_price {
+        Some(effective_price) => format!("{} ({})", level.exact_price, effective_price),
+        None => level.exact_price.clone(),
+    }
+}
+
 pub fn print_summary_as_table(summary: Summary) {
     clear_terminal();
 
@@ -26,23 +82,61 @@ pub fn print_summary_as_table(summary: Summary) {
             Cell::new("Ask Exchange"),
         ]);
 
-    let length = min(summary.bids.len(), summary.asks.len());
+    // Sides can have different lengths, even be empty
+    let length = max(summary.bids.len(), summary.asks.len());
 
     for i in 0..length {
-        let bid = summary.bids[i].clone();
+        let (bid_exchange, bid_amount, bid_price) = match summary.bids.get(i) {
+            Some(bid) => (
+                format_level_exchange(bid),
+                bid.exact_amount.clone(),
+                format_level_price(bid),
+            ),
+            None => Default::default(),
+        };
 
-        let ask = summary.asks[i].clone();
+        let (ask_exchange, ask_amount, ask_price) = match summary.asks.get(i) {
+            Some(ask) => (
+                format_level_exchange(ask),
+                ask.exact_amount.clone(),
+                format_level_price(ask),
+            ),
+            None => Default::default(),
+        };
 
         table.add_row(vec![
-            bid.exchange,
-            bid.amount.to_string(),
-            bid.price.to_string(),
-            ask.price.to_string(),
-            ask.amount.to_string(),
-            ask.exchange,
+            bid_exchange,
+            bid_amount,
+            bid_price,
+            ask_price,
+            ask_amount,
+            ask_exchange,
         ]);
     }
 
-    println!("Current spread: {}", summary.spread);
+    if summary.sequence > 0 {
+        println!("Summary #{}", summary.sequence);
+    }
+    for status in summary.exchange_statuses.iter() {
+        println!("{}", format_exchange_status(status));
+    }
+    if summary.cached {
+        println!("Cached summary, data age: {} ms", summary.data_age_ms);
+    }
+    // Exact spread, if it's calculated by the server
+    let spread = summary
+        .exact_spread
+        .or_else(|| summary.spread.map(|spread| spread.to_string()));
+    match spread {
+        Some(spread) if summary.crossed => println!("Current spread: {} (crossed book)", spread),
+        Some(spread) => println!("Current spread: {}", spread),
+        None => println!("Current spread: - (one-sided book)"),
+    }
+    let effective_spread = summary
+        .exact_effective_spread
+        .or_else(|| summary.effective_spread.map(|spread| spread.to_string()));
+    if let Some(effective_spread) = effective_spread {
+        println!("Spread after fees: {}", effective_spread);
+    }
     println!("{}", table);
 }
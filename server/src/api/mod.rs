@@ -1,97 +1,493 @@
-use std::sync::Arc;
+use std::{
+    collections::{HashMap, HashSet},
+    net::SocketAddr,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+};
 
-use flume::{r#async::RecvStream, Receiver, Sender};
-use tokio::sync::Mutex;
+use flume::Receiver;
+use futures_util::Stream;
+use tokio::sync::{watch, Mutex, RwLock};
 use tonic::{transport::Server, Request, Response, Status};
 
 use orderbook::orderbook_aggregator_server::{OrderbookAggregator, OrderbookAggregatorServer};
-use orderbook::{Empty, Summary};
+use orderbook::{BookUpdate, Quote, QuoteRequest, Side, Summary, SummaryRequest};
 
+use crate::data_sources::{is_supported_exchange, symbol::Pair};
+use crate::decimal::Decimal;
 use crate::helpers::get_env_var_or_default;
+use crate::summary::{
+    calculate_quote, calculate_summary, BookUpdatesBuilder, ExchangeOrderbooks, QuoteSide,
+    SummaryView,
+};
 
 pub mod orderbook {
     tonic::include_proto!("orderbook");
 }
 
-type ClientSender = Sender<Result<Summary, Status>>;
+/// Latest summary which is not received by client yet. Newer summaries replace older ones,
+/// so slow clients never block the broadcast and always get the most recent data.
+type ClientSender = watch::Sender<Option<Summary>>;
+
+struct Client {
+    /// Remote address, to tell clients apart in logs
+    address: String,
+    tx: ClientSender,
+    view: SummaryView,
+    /// Number of summaries sent to the client
+    sent: u64,
+    /// Number of summaries received by the client, the rest were replaced by newer ones
+    received: Arc<AtomicU64>,
+    /// Number of skipped summaries when the client was reported as slow last time
+    reported_skipped: u64,
+}
+
+impl Client {
+    /// Summaries replaced by newer ones before the client received them.
+    /// The latest sent summary is not counted, as it can still be received.
+    fn skipped(&self) -> u64 {
+        let received = self.received.load(Ordering::Relaxed);
+        self.sent.saturating_sub(received).saturating_sub(1)
+    }
+}
+
+type ClientStream<T> = Pin<Box<dyn Stream<Item = Result<T, Status>> + Send>>;
+
+/// Stream of messages built from the latest client summaries, which ends when the client is removed.
+/// Summaries for which `build_message` returns `None` are skipped.
+fn get_client_stream<T, F>(
+    rx: watch::Receiver<Option<Summary>>,
+    received: Arc<AtomicU64>,
+    build_message: F,
+) -> ClientStream<T>
+where
+    T: Send + 'static,
+    F: FnMut(Summary) -> Option<T> + Send + 'static,
+{
+    Box::pin(futures_util::stream::unfold(
+        (rx, received, build_message),
+        |(mut rx, received, mut build_message)| async move {
+            loop {
+                rx.changed().await.ok()?;
+
+                let summary = rx.borrow_and_update().clone();
+                if let Some(summary) = summary {
+                    received.fetch_add(1, Ordering::Relaxed);
+
+                    if let Some(message) = build_message(summary) {
+                        return Some((Ok(message), (rx, received, build_message)));
+                    }
+                }
+            }
+        },
+    ))
+}
+
+type Clients = Arc<Mutex<Vec<Client>>>;
+
+/// Latest orderbooks of a pair, `None` until the first update
+type LatestOrderbooks = Arc<RwLock<Option<ExchangeOrderbooks>>>;
+
+struct PairState {
+    clients: Clients,
+    latest_orderbooks: LatestOrderbooks,
+}
 
 struct OrderbookAggregatorService {
-    clients: Arc<Mutex<Vec<ClientSender>>>,
+    /// State of every served pair
+    pairs: HashMap<Pair, PairState>,
+    /// Pair used when request doesn't specify any
+    default_pair: Pair,
+    /// Depth of data sources, clients can't request more
+    max_depth: u16,
+    /// Price bucket size for clients which don't request any
+    default_tick_size: Decimal,
+    data_lifetime_ms: u64,
+}
+
+impl OrderbookAggregatorService {
+    /// Pair of request symbol, or the default one if symbol is empty
+    fn get_pair(&self, symbol: &str) -> Result<Pair, String> {
+        if symbol.is_empty() {
+            return Ok(self.default_pair.clone());
+        }
+
+        Pair::parse(symbol).map_err(|error| error.to_string())
+    }
+
+    /// Request pair and its state. Fails if symbol is invalid, or the pair is not served.
+    // Handlers return the status as is, so it's not boxed
+    #[allow(clippy::result_large_err)]
+    fn get_pair_state(&self, symbol: &str) -> Result<(Pair, &PairState), Status> {
+        let pair = self.get_pair(symbol).map_err(Status::invalid_argument)?;
+
+        let pair_state = self.pairs.get(&pair).ok_or_else(|| {
+            Status::not_found(format!("{} is not aggregated by this server", pair))
+        })?;
+
+        Ok((pair, pair_state))
+    }
+
+    /// Adds client to the broadcast. Its slot contains the latest summary from the start, if there is fresh data.
+    async fn add_client(
+        &self,
+        pair: &Pair,
+        pair_state: &PairState,
+        address: Option<SocketAddr>,
+        view: SummaryView,
+    ) -> (watch::Receiver<Option<Summary>>, Arc<AtomicU64>) {
+        let (tx, rx) = watch::channel(None);
+        let received = Arc::new(AtomicU64::new(0));
+        let mut sent = 0;
+
+        // Clients are locked while sending the cached summary, so no update can be missed between them
+        let mut clients = pair_state.clients.lock().await;
+
+        let orderbooks = pair_state.latest_orderbooks.read().await.clone();
+        let cached_summary = orderbooks
+            .and_then(|orderbooks| calculate_summary(&orderbooks, &view, self.data_lifetime_ms));
+        if let Some(mut summary) = cached_summary {
+            summary.cached = true;
+            tx.send_replace(Some(summary));
+            sent += 1;
+        }
+
+        clients.push(Client {
+            address: address.map_or_else(|| "unknown".to_string(), |address| address.to_string()),
+            tx,
+            view,
+            sent,
+            received: received.clone(),
+            reported_skipped: 0,
+        });
+        println!(
+            "New {} client connected. Total of {} {} clients connected",
+            pair,
+            clients.len(),
+            pair
+        );
+        drop(clients);
+
+        (rx, received)
+    }
+
+    /// Client view of the aggregated orderbook. Fails if request contains unknown exchanges or invalid tick size.
+    fn get_summary_view(&self, request: SummaryRequest) -> Result<SummaryView, String> {
+        let depth = if request.depth == 0 {
+            self.max_depth
+        } else {
+            request.depth.min(self.max_depth as u32) as u16
+        };
+
+        let tick_size = match request.tick_size {
+            Some(tick_size) => tick_size
+                .trim()
+                .parse::<Decimal>()
+                .ok()
+                .filter(|tick_size| !tick_size.is_negative())
+                .ok_or_else(|| format!("Invalid tick size `{}`", tick_size))?,
+            None => self.default_tick_size,
+        };
+
+        let (include_exchanges, exclude_exchanges) =
+            get_exchange_filters(request.include_exchanges, request.exclude_exchanges)?;
+
+        Ok(SummaryView {
+            depth,
+            include_exchanges,
+            exclude_exchanges,
+            merge_levels: request.merge_levels,
+            tick_size,
+            fee_adjusted: request.fee_adjusted,
+        })
+    }
+
+    /// View of all available levels of the exchanges used by quote
+    fn get_quote_view(&self, request: &QuoteRequest) -> Result<SummaryView, String> {
+        let (include_exchanges, exclude_exchanges) = get_exchange_filters(
+            request.include_exchanges.clone(),
+            request.exclude_exchanges.clone(),
+        )?;
+
+        Ok(SummaryView {
+            depth: self.max_depth,
+            include_exchanges,
+            exclude_exchanges,
+            merge_levels: false,
+            tick_size: Decimal::ZERO,
+            fee_adjusted: request.fee_adjusted,
+        })
+    }
+}
+
+/// Normalized exchange names of request filters. Fails if some exchange is unknown.
+fn get_exchange_filters(
+    include_exchanges: Vec<String>,
+    exclude_exchanges: Vec<String>,
+) -> Result<(Vec<String>, Vec<String>), String> {
+    let normalize = |exchanges: Vec<String>| -> Vec<String> {
+        exchanges
+            .iter()
+            .map(|exchange| exchange.trim().to_lowercase())
+            .collect()
+    };
+    let include_exchanges = normalize(include_exchanges);
+    let exclude_exchanges = normalize(exclude_exchanges);
+
+    if let Some(exchange) = include_exchanges
+        .iter()
+        .chain(exclude_exchanges.iter())
+        .find(|exchange| !is_supported_exchange(exchange))
+    {
+        return Err(format!("Unknown exchange `{}`", exchange));
+    }
+
+    Ok((include_exchanges, exclude_exchanges))
 }
 
 #[tonic::async_trait]
 impl OrderbookAggregator for OrderbookAggregatorService {
-    type BookSummaryStream = RecvStream<'static, Result<Summary, Status>>;
+    type BookSummaryStream = ClientStream<Summary>;
+    type BookUpdatesStream = ClientStream<BookUpdate>;
 
     async fn book_summary(
         &self,
-        _request: Request<Empty>,
+        request: Request<SummaryRequest>,
     ) -> Result<Response<Self::BookSummaryStream>, Status> {
-        let (tx, rx) = flume::bounded(0);
+        let address = request.remote_addr();
+        let request = request.into_inner();
+        let (pair, pair_state) = self.get_pair_state(&request.symbol)?;
 
-        let mut clients = self.clients.lock().await;
-        clients.push(tx.clone());
-        println!(
-            "New client connected. Total of {} clients connected",
-            clients.len()
-        );
-        drop(clients);
+        let view = self
+            .get_summary_view(request)
+            .map_err(Status::invalid_argument)?;
+
+        let (rx, received) = self.add_client(&pair, pair_state, address, view).await;
+
+        Ok(Response::new(get_client_stream(rx, received, Some)))
+    }
+
+    async fn book_updates(
+        &self,
+        request: Request<SummaryRequest>,
+    ) -> Result<Response<Self::BookUpdatesStream>, Status> {
+        let address = request.remote_addr();
+        let request = request.into_inner();
+        let (pair, pair_state) = self.get_pair_state(&request.symbol)?;
+
+        let view = self
+            .get_summary_view(request)
+            .map_err(Status::invalid_argument)?;
+
+        let (rx, received) = self.add_client(&pair, pair_state, address, view).await;
+
+        // Changes are calculated against the last summary received by the client,
+        // so updates can't be lost, even if summaries are skipped for a slow client
+        let mut updates_builder = BookUpdatesBuilder::default();
+        let stream = get_client_stream(rx, received, move |summary| updates_builder.build(summary));
+
+        Ok(Response::new(stream))
+    }
+
+    async fn get_summary(
+        &self,
+        request: Request<SummaryRequest>,
+    ) -> Result<Response<Summary>, Status> {
+        let request = request.into_inner();
+        let (pair, pair_state) = self.get_pair_state(&request.symbol)?;
+
+        let view = self
+            .get_summary_view(request)
+            .map_err(Status::invalid_argument)?;
 
-        Ok(Response::new(rx.into_stream()))
+        let orderbooks = pair_state.latest_orderbooks.read().await.clone();
+
+        orderbooks
+            .and_then(|orderbooks| calculate_summary(&orderbooks, &view, self.data_lifetime_ms))
+            .map(Response::new)
+            .ok_or_else(|| Status::unavailable(format!("No fresh {} orderbook data", pair)))
+    }
+
+    async fn quote_execution(
+        &self,
+        request: Request<QuoteRequest>,
+    ) -> Result<Response<Quote>, Status> {
+        let request = request.into_inner();
+        let (pair, pair_state) = self.get_pair_state(&request.symbol)?;
+
+        let side = match Side::from_i32(request.side) {
+            Some(Side::Buy) => QuoteSide::Buy,
+            Some(Side::Sell) => QuoteSide::Sell,
+            Some(Side::Unspecified) => return Err(Status::invalid_argument("Side is not set")),
+            None => {
+                return Err(Status::invalid_argument(format!(
+                    "Unknown side `{}`",
+                    request.side
+                )))
+            }
+        };
+        let quantity = request
+            .quantity
+            .trim()
+            .parse::<Decimal>()
+            .ok()
+            .filter(|quantity| quantity.is_positive())
+            .ok_or_else(|| {
+                Status::invalid_argument(format!("Invalid quantity `{}`", request.quantity))
+            })?;
+
+        let view = self
+            .get_quote_view(&request)
+            .map_err(Status::invalid_argument)?;
+
+        let orderbooks = pair_state.latest_orderbooks.read().await.clone();
+
+        orderbooks
+            .and_then(|orderbooks| {
+                calculate_quote(&orderbooks, &view, side, quantity, self.data_lifetime_ms)
+            })
+            .map(Response::new)
+            .ok_or_else(|| Status::unavailable(format!("No fresh {} orderbook data", pair)))
     }
 }
 
 const DEFAULT_PORT: u16 = 10000;
+const DEFAULT_TICK_SIZE: Decimal = Decimal::ZERO; // no price grouping
 
-pub async fn serve(summary_rx: Receiver<Summary>) -> Result<(), Box<dyn std::error::Error>> {
-    let port = get_env_var_or_default("PORT", DEFAULT_PORT);
-    let addr = format!("[::1]:{}", port).parse()?;
+// Slow client is reported every time it skips this many more summaries
+const SKIPPED_SUMMARIES_REPORT_STEP: u64 = 100;
 
-    let clients: Arc<Mutex<Vec<ClientSender>>> = Arc::new(Mutex::new(vec![]));
-
-    let _main_server_thread = {
-        let clients = clients.clone();
-        tokio::spawn(async move {
-            while !summary_rx.is_disconnected() {
-                let summary_drain = summary_rx.drain();
-                let last = summary_drain.last();
-                if let Some(summary) = last {
-                    let mut clients = clients.lock().await;
-                    let mut clients_to_remove = vec![];
-
-                    for (i, client) in clients.iter().enumerate() {
-                        if client.is_disconnected() {
-                            clients_to_remove.push(i);
-                            continue;
-                        }
-
-                        match client.send_async(Ok(summary.clone())).await {
-                            Ok(_) => (),
-                            Err(e) => {
-                                println!("Error sending summary to client: {}", e);
-                                clients_to_remove.push(i);
-                            }
-                        }
-                    }
+/// Sends summary of every orderbooks update of the pair to all its clients,
+/// and keeps the latest orderbooks for one-shot requests
+fn spawn_broadcast_thread(
+    pair: Pair,
+    orderbooks_rx: Receiver<ExchangeOrderbooks>,
+    clients: Clients,
+    latest_orderbooks: LatestOrderbooks,
+    data_lifetime_ms: u64,
+) -> tokio::task::JoinHandle<()> {
+    tokio::spawn(async move {
+        while let Ok(orderbooks) = orderbooks_rx.recv_async().await {
+            // Only the latest orderbooks matter
+            let orderbooks = orderbooks_rx.drain().last().unwrap_or(orderbooks);
 
-                    if !clients_to_remove.is_empty() {
-                        for i in clients_to_remove.iter().rev() {
-                            clients.remove(*i);
-                        }
-                        println!(
-                            "{} clients disconnected. Clients left: {}",
-                            clients_to_remove.len(),
-                            clients.len()
-                        );
-                    }
+            *latest_orderbooks.write().await = Some(orderbooks.clone());
+
+            // Summaries are calculated once per view, without locking clients, so new clients
+            // are not blocked. Clients added meanwhile get these orderbooks as cached summary.
+            let views: HashSet<SummaryView> = clients
+                .lock()
+                .await
+                .iter()
+                .map(|client| client.view.clone())
+                .collect();
+            let summaries: HashMap<SummaryView, Summary> = views
+                .into_iter()
+                .filter_map(|view| {
+                    // If all data of view exchanges is too old, or there is not enough data
+                    calculate_summary(&orderbooks, &view, data_lifetime_ms)
+                        .map(|summary| (view, summary))
+                })
+                .collect();
+
+            let mut clients = clients.lock().await;
+            let mut clients_to_remove = vec![];
+
+            for (i, client) in clients.iter_mut().enumerate() {
+                if client.tx.is_closed() {
+                    clients_to_remove.push(i);
+                    continue;
+                }
+
+                let summary = match summaries.get(&client.view) {
+                    Some(summary) => summary.clone(),
+                    None => continue,
+                };
+
+                // Never waits for the client, replaces its previous summary if it's not received yet
+                client.tx.send_replace(Some(summary));
+                client.sent += 1;
+
+                let skipped = client.skipped();
+                if skipped >= client.reported_skipped + SKIPPED_SUMMARIES_REPORT_STEP {
+                    println!(
+                        "{} client {} is too slow: {} of {} summaries were skipped",
+                        pair, client.address, skipped, client.sent
+                    );
+                    client.reported_skipped = skipped;
                 }
             }
 
-            println!("Main server thread finished");
-        })
-    };
+            if !clients_to_remove.is_empty() {
+                for i in clients_to_remove.iter().rev() {
+                    let client = clients.remove(*i);
+                    let received = client.received.load(Ordering::Relaxed);
+                    println!(
+                        "{} client {} received {} of {} summaries, {} were skipped as it was too slow",
+                        pair,
+                        client.address,
+                        received,
+                        client.sent,
+                        client.sent.saturating_sub(received)
+                    );
+                }
+                println!(
+                    "{} {} clients disconnected. Clients left: {}",
+                    clients_to_remove.len(),
+                    pair,
+                    clients.len()
+                );
+            }
+        }
 
-    let orderbook_aggregator = OrderbookAggregatorService { clients };
+        println!("{} broadcast thread finished", pair);
+    })
+}
+
+pub async fn serve(
+    orderbooks_rxs: Vec<(Pair, Receiver<ExchangeOrderbooks>)>,
+    max_depth: u16,
+    data_lifetime_ms: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let port = get_env_var_or_default("PORT", DEFAULT_PORT);
+    let addr = format!("[::1]:{}", port).parse()?;
+
+    let default_tick_size = get_env_var_or_default("TICK_SIZE", DEFAULT_TICK_SIZE);
+    if default_tick_size.is_negative() {
+        return Err(format!("Invalid TICK_SIZE `{}`", default_tick_size).into());
+    }
+
+    let default_pair = orderbooks_rxs
+        .first()
+        .map(|(pair, _)| pair.clone())
+        .ok_or("No symbols to serve")?;
+
+    let mut pairs = HashMap::new();
+    for (pair, orderbooks_rx) in orderbooks_rxs {
+        let pair_state = PairState {
+            clients: Arc::new(Mutex::new(vec![])),
+            latest_orderbooks: Arc::new(RwLock::new(None)),
+        };
+        spawn_broadcast_thread(
+            pair.clone(),
+            orderbooks_rx,
+            pair_state.clients.clone(),
+            pair_state.latest_orderbooks.clone(),
+            data_lifetime_ms,
+        );
+        pairs.insert(pair, pair_state);
+    }
+
+    let orderbook_aggregator = OrderbookAggregatorService {
+        pairs,
+        default_pair,
+        max_depth,
+        default_tick_size,
+        data_lifetime_ms,
+    };
 
     let svc = OrderbookAggregatorServer::new(orderbook_aggregator);
 
@@ -0,0 +1,64 @@
+use std::error::Error;
+
+use url::Url;
+
+use super::output_data_format::ExchangeOrderbookData;
+use super::symbol::Pair;
+
+/// Result of parsing a single message received from an exchange
+pub enum AdapterMessage {
+    /// New orderbook state of the pair, which should be sent to the summary thread of this pair
+    Orderbook(Pair, ExchangeOrderbookData),
+    /// Exchange asked us to reconnect
+    Reconnect,
+    /// Local orderbook is broken, unsubscribe and subscribe again to get a new snapshot.
+    /// All pairs are resubscribed, so updates of the previous subscription
+    /// which are received before the new snapshot must be skipped.
+    Resubscribe,
+    /// Message doesn't contain orderbook data (subscription confirmation, error, etc.)
+    Ignore,
+}
+
+/// Everything that is exchange specific lives behind this trait.
+/// Connection handling (reconnects, pings, connection age) is done by `connection::spawn_thread`.
+/// A single adapter (and connection) serves all pairs of the exchange.
+pub trait ExchangeAdapter: Send {
+    /// Exchange name, used as `ExchangeOrderbookData::exchange`
+    fn name(&self) -> &'static str;
+
+    fn url(&self) -> Url;
+
+    /// Called every time a new connection is established, before subscribing.
+    /// Adapters which maintain a local orderbook should reset it here.
+    fn on_connected(&mut self) {}
+
+    /// Messages which should be sent right after connection is established
+    fn subscribe_messages(&self) -> Vec<String> {
+        vec![]
+    }
+
+    /// Messages which cancel subscriptions, used to resubscribe without reconnecting
+    fn unsubscribe_messages(&self) -> Vec<String> {
+        vec![]
+    }
+
+    /// Application level ping message, sent when exchange is silent for too long.
+    /// If `None`, WebSocket PING frame is used instead.
+    fn ping_message(&self) -> Option<String> {
+        None
+    }
+
+    /// Exchange closes connections older than this. Most exchanges have no documented limit,
+    /// but long-lived connections are reconnected once a day anyway.
+    fn connection_age_limit_seconds(&self) -> u64 {
+        24 * 60 * 60
+    }
+
+    /// Maximal orderbook depth supported by exchange API. `None` means unlimited.
+    fn depth_limit(&self) -> Option<u16>;
+
+    fn parse_message(
+        &mut self,
+        data: &[u8],
+    ) -> Result<AdapterMessage, Box<dyn Error + Send + Sync>>;
+}
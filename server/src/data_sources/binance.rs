@@ -1,8 +1,12 @@
-use serde::Deserialize;
-use tungstenite::{connect, Message};
+use std::{collections::HashMap, error::Error};
+
+use serde::{de::DeserializeOwned, Deserialize};
 use url::Url;
 
-use super::output_data_format::ExchangeOrderbookData;
+use super::adapter::{AdapterMessage, ExchangeAdapter};
+use super::local_orderbook::LocalOrderbook;
+use super::snapshot::request_snapshot;
+use super::symbol::Pair;
 use crate::helpers::get_env_var_or_default;
 
 #[derive(Deserialize, Debug)]
@@ -12,11 +16,31 @@ pub struct BinanceApiOrderBookMessage {
     pub asks: Vec<(String, String)>,
 }
 
-impl BinanceApiOrderBookMessage {
-    pub fn trim(&mut self, depth: u16) {
-        self.bids.truncate(depth as usize);
-        self.asks.truncate(depth as usize);
-    }
+/// Diff. depth stream event
+#[derive(Deserialize, Debug)]
+struct BinanceApiDepthUpdateMessage {
+    /// Event time in milliseconds
+    #[serde(rename = "E")]
+    event_time: u64,
+    /// First update ID in event
+    #[serde(rename = "U")]
+    first_update_id: u64,
+    /// Final update ID in event
+    #[serde(rename = "u")]
+    final_update_id: u64,
+    #[serde(rename = "b")]
+    bids: Vec<(String, String)>,
+    #[serde(rename = "a")]
+    asks: Vec<(String, String)>,
+}
+
+/// REST API `/depth` response
+#[derive(Deserialize, Debug)]
+struct BinanceApiDepthSnapshot {
+    #[serde(rename = "lastUpdateId")]
+    last_update_id: u64,
+    bids: Vec<(String, String)>,
+    asks: Vec<(String, String)>,
 }
 
 #[derive(Deserialize, Debug)]
@@ -30,120 +54,551 @@ struct BinanceApiErrorMessage {
     error: BinanceApiError,
 }
 
+/// Combined stream payload, which contains the stream name
+#[derive(Deserialize, Debug)]
+struct BinanceApiStreamMessage<T> {
+    /// Like `ethbtc@depth10@100ms`
+    stream: String,
+    data: T,
+}
+
+pub const BINANCE_EXCHANGE_NAME: &str = "binance";
 const BINANCE_API_BASE_URL: &str = "wss://stream.binance.com:9443/ws";
+const BINANCE_REST_API_BASE_URL: &str = "https://api.binance.com/api/v3";
 const BINANCE_SUPPORTED_DEPTH_LIMITS: [u16; 3] = [5, 10, 20];
+// Maximal depth of REST API snapshot
+const BINANCE_SNAPSHOT_DEPTH_LIMIT: u16 = 5000;
+// Events of 100 seconds. If snapshot takes longer, the orderbook is resynced.
+const BINANCE_MAX_BUFFERED_EVENTS: usize = 1000;
 
-// Maximal age of connection is 24 hours
-const BINANCE_CONNECTION_AGE_LIMIT_SECONDS: u64 = 24 * 60 * 60;
-const BINANCE_RECONNECTION_FREQUENCY_SECONDS: u64 = BINANCE_CONNECTION_AGE_LIMIT_SECONDS - 60;
+/// Partial book depth stream adapter if `BINANCE_FULL_DEPTH` is not set,
+/// otherwise local orderbook maintained from diff. depth stream
+pub fn new_adapter(pairs: &[Pair], depth: u16) -> Box<dyn ExchangeAdapter> {
+    let full_depth = get_env_var_or_default("BINANCE_FULL_DEPTH", false);
 
-pub fn spawn_thread(
-    symbol: String,
-    depth: u16,
-    tx: flume::Sender<ExchangeOrderbookData>,
-) -> tokio::task::JoinHandle<()> {
-    let effective_depth = {
-        BINANCE_SUPPORTED_DEPTH_LIMITS
+    if full_depth {
+        Box::new(BinanceFullDepthAdapter::new(pairs, depth))
+    } else {
+        Box::new(BinanceAdapter::new(pairs, depth))
+    }
+}
+
+fn get_api_url() -> Url {
+    let url = get_env_var_or_default("BINANCE_API_BASE_URL", BINANCE_API_BASE_URL.to_string());
+    Url::parse(&url).expect("Failed to parse Binance API URL")
+}
+
+/// Streams of all pairs are subscribed on a single connection.
+/// Payloads of raw streams don't contain stream name, so combined payloads are requested.
+fn get_subscribe_messages(streams: impl Iterator<Item = String>) -> Vec<String> {
+    let streams: Vec<String> = streams.map(|stream| format!(r#""{}""#, stream)).collect();
+
+    vec![
+        r#"{"method": "SET_PROPERTY", "params": ["combined", true], "id": 1}"#.to_string(),
+        format!(
+            r#"{{"method": "SUBSCRIBE", "params": [{}], "id": 2}}"#,
+            streams.join(", ")
+        ),
+    ]
+}
+
+/// Incoming message, common for all streams
+enum BinanceMessage<T> {
+    /// Stream payload with Binance symbol of the stream, like `ethbtc`
+    Data(String, T),
+    Other,
+}
+
+fn parse_binance_message<T: DeserializeOwned>(
+    data: &[u8],
+) -> Result<BinanceMessage<T>, Box<dyn Error + Send + Sync>> {
+    let error = match serde_json::from_slice::<BinanceApiStreamMessage<T>>(data) {
+        Ok(message) => {
+            let symbol = message.stream.split('@').next().unwrap_or_default();
+            return Ok(BinanceMessage::Data(symbol.to_string(), message.data));
+        }
+        Err(error) => error,
+    };
+
+    if let Ok(error_message) = serde_json::from_slice::<BinanceApiErrorMessage>(data) {
+        println!(
+            "Error from Binance API: {} (code: {})",
+            error_message.error.msg, error_message.error.code
+        );
+        return Ok(BinanceMessage::Other);
+    }
+
+    // Responses to requests, like `SUBSCRIBE`, contain request ID
+    let message: serde_json::Value = serde_json::from_slice(data)?;
+    if message.get("id").is_some() {
+        return Ok(BinanceMessage::Other);
+    }
+
+    Err(error.into())
+}
+
+/// Uses partial book depth stream, so only up to 20 levels are available
+pub struct BinanceAdapter {
+    url: Url,
+    /// Stream name without symbol, like `@depth10@100ms`
+    stream_suffix: String,
+    /// Pairs by Binance symbol, like `ethbtc`
+    pairs: HashMap<String, Pair>,
+}
+
+impl BinanceAdapter {
+    pub fn new(pairs: &[Pair], depth: u16) -> Self {
+        let effective_depth = BINANCE_SUPPORTED_DEPTH_LIMITS
             .iter()
             .find(|&&limit| limit >= depth)
-            .unwrap_or_else(|| {
-                println!(
-                    "[WARNING] Binance API supports only depth <= {}. Only first {} levels will be used.",
-                    BINANCE_SUPPORTED_DEPTH_LIMITS[2], BINANCE_SUPPORTED_DEPTH_LIMITS[2]
-                );
-
-                &BINANCE_SUPPORTED_DEPTH_LIMITS[2]
-            }).to_owned()
-
-        // "Unlimited" depth support may be implemented with more complex logic
-        // Docs: https://github.com/binance/binance-spot-api-docs/blob/master/web-socket-streams.md#how-to-manage-a-local-order-book-correctly
-        // But it's not the goal of this app.
-    };
+            .unwrap_or(&BINANCE_SUPPORTED_DEPTH_LIMITS[2]);
+
+        // For "unlimited" depth see `BinanceFullDepthAdapter`
+
+        let pairs = pairs
+            .iter()
+            .map(|pair| (pair.exchange_symbol(BINANCE_EXCHANGE_NAME), pair.clone()))
+            .collect();
+
+        Self {
+            url: get_api_url(),
+            stream_suffix: format!("@depth{}@100ms", effective_depth),
+            pairs,
+        }
+    }
+}
+
+impl ExchangeAdapter for BinanceAdapter {
+    fn name(&self) -> &'static str {
+        BINANCE_EXCHANGE_NAME
+    }
+
+    fn url(&self) -> Url {
+        self.url.clone()
+    }
+
+    fn subscribe_messages(&self) -> Vec<String> {
+        let streams = self
+            .pairs
+            .keys()
+            .map(|symbol| format!("{}{}", symbol, self.stream_suffix));
+
+        get_subscribe_messages(streams)
+    }
+
+    fn depth_limit(&self) -> Option<u16> {
+        Some(BINANCE_SUPPORTED_DEPTH_LIMITS[2])
+    }
+
+    fn parse_message(
+        &mut self,
+        data: &[u8],
+    ) -> Result<AdapterMessage, Box<dyn Error + Send + Sync>> {
+        match parse_binance_message::<BinanceApiOrderBookMessage>(data)? {
+            BinanceMessage::Data(symbol, orderbook) => {
+                let pair = self
+                    .pairs
+                    .get(&symbol)
+                    .ok_or_else(|| format!("Unexpected Binance symbol: {}", symbol))?;
+
+                Ok(AdapterMessage::Orderbook(
+                    pair.clone(),
+                    orderbook.try_into()?,
+                ))
+            }
+            BinanceMessage::Other => Ok(AdapterMessage::Ignore),
+        }
+    }
+}
+
+/// Synchronization of local orderbook with the diff. depth stream
+enum BinanceOrderbookSync {
+    /// REST API snapshot is requested, stream events are buffered until it arrives
+    WaitingForSnapshot {
+        snapshot_rx: flume::Receiver<BinanceApiDepthSnapshot>,
+        buffered_events: Vec<BinanceApiDepthUpdateMessage>,
+    },
+    /// Local orderbook is up to date with update `last_update_id`
+    Synced { last_update_id: u64 },
+}
+
+/// Local orderbook of a single pair
+struct BinanceBook {
+    pair: Pair,
+    snapshot_url: Url,
+    orderbook: LocalOrderbook,
+    sync: BinanceOrderbookSync,
+}
+
+impl BinanceBook {
+    fn new(pair: &Pair, symbol: &str) -> Self {
+        let rest_base_url = get_env_var_or_default(
+            "BINANCE_REST_API_BASE_URL",
+            BINANCE_REST_API_BASE_URL.to_string(),
+        );
+        let snapshot_url = format!(
+            "{}/depth?symbol={}&limit={}",
+            rest_base_url,
+            symbol.to_uppercase(),
+            BINANCE_SNAPSHOT_DEPTH_LIMIT
+        );
+        let snapshot_url = Url::parse(&snapshot_url).expect("Failed to parse Binance REST API URL");
+
+        // Snapshot is requested in `on_connected`
+        let sync = BinanceOrderbookSync::WaitingForSnapshot {
+            snapshot_rx: flume::bounded(1).1,
+            buffered_events: vec![],
+        };
+
+        Self {
+            pair: pair.clone(),
+            snapshot_url,
+            orderbook: LocalOrderbook::default(),
+            sync,
+        }
+    }
 
-    let base_url = get_env_var_or_default("BINANCE_API_BASE_URL", BINANCE_API_BASE_URL.to_string());
-    let url = format!("{}/{}@depth{}@100ms", base_url, symbol, depth);
-    let url = Url::parse(&url).expect("Failed to parse Binance API URL");
+    /// Drops local orderbook and requests a new snapshot.
+    /// Events received until the snapshot arrives are buffered.
+    fn resync(&mut self) {
+        self.orderbook.clear();
+        self.sync = BinanceOrderbookSync::WaitingForSnapshot {
+            snapshot_rx: request_snapshot(BINANCE_EXCHANGE_NAME, self.snapshot_url.clone()),
+            buffered_events: vec![],
+        };
+    }
 
-    tokio::spawn(async move {
-        let mut should_reconnect = true;
+    /// Applies stream event on top of the orderbook which is up to date with update `last_update_id`.
+    /// Returns new last update ID, or `None` if some events were missed and orderbook has to be resynced.
+    fn apply_event(
+        &mut self,
+        last_update_id: u64,
+        event: &BinanceApiDepthUpdateMessage,
+    ) -> Result<Option<u64>, Box<dyn Error + Send + Sync>> {
+        if event.final_update_id <= last_update_id {
+            // Event is older than the orderbook
+            return Ok(Some(last_update_id));
+        }
 
-        while should_reconnect {
-            let (mut socket, _) = connect(url.clone()).expect("Can't connect to Binance API");
+        if event.first_update_id > last_update_id + 1 {
+            println!(
+                "Binance {} orderbook gap: expected update {}, got {}..{}. Resyncing...",
+                self.pair,
+                last_update_id + 1,
+                event.first_update_id,
+                event.final_update_id
+            );
+            return Ok(None);
+        }
 
-            let connection_time = std::time::Instant::now();
+        self.orderbook.update_bids(&event.bids)?;
+        self.orderbook.update_asks(&event.asks)?;
 
-            loop {
-                if !socket.can_read() {
-                    println!("Binance API connection is closed by server. Reconnecting...");
-                    should_reconnect = true;
-                    break;
+        Ok(Some(event.final_update_id))
+    }
+
+    /// Returns `false` if the orderbook is not synced yet
+    fn handle_event(
+        &mut self,
+        event: BinanceApiDepthUpdateMessage,
+    ) -> Result<bool, Box<dyn Error + Send + Sync>> {
+        match &mut self.sync {
+            BinanceOrderbookSync::Synced { last_update_id } => {
+                let last_update_id = *last_update_id;
+
+                match self.apply_event(last_update_id, &event)? {
+                    Some(last_update_id) => {
+                        self.sync = BinanceOrderbookSync::Synced { last_update_id };
+                    }
+                    None => {
+                        self.resync();
+                        return self.handle_event(event);
+                    }
                 }
+            }
+            BinanceOrderbookSync::WaitingForSnapshot {
+                snapshot_rx,
+                buffered_events,
+            } => {
+                buffered_events.push(event);
 
-                if connection_time.elapsed().as_secs() > BINANCE_RECONNECTION_FREQUENCY_SECONDS {
-                    println!("Binance API connection is too old. Reconnecting...");
-                    should_reconnect = true;
-                    break;
+                if buffered_events.len() > BINANCE_MAX_BUFFERED_EVENTS {
+                    println!(
+                        "Binance {} orderbook snapshot is not received in time. Resyncing...",
+                        self.pair
+                    );
+                    self.resync();
+                    return Ok(false);
                 }
 
-                let message = socket
-                    .read_message()
-                    .expect("Error reading message from Binance API");
+                let snapshot = match snapshot_rx.try_recv() {
+                    Ok(snapshot) => snapshot,
+                    Err(_) => return Ok(false),
+                };
 
-                if message.is_ping() {
-                    match socket.write_message(Message::Pong(message.into_data())) {
-                        Ok(_) => {
-                            continue;
-                        }
-                        Err(error) => {
-                            println!(
-                                "Error sending PONG message to Binance API: {}. Reconnecting...",
-                                error
-                            );
-                            // if we don't send PONG, the connection will be closed by server 10 minutes later PING
-                            should_reconnect = true;
-                            break;
+                if snapshot.last_update_id < buffered_events[0].first_update_id {
+                    // Snapshot is older than the first buffered event, so it can't be used
+                    *snapshot_rx =
+                        request_snapshot(BINANCE_EXCHANGE_NAME, self.snapshot_url.clone());
+                    return Ok(false);
+                }
+
+                let buffered_events = std::mem::take(buffered_events);
+
+                self.orderbook.clear();
+                self.orderbook.update_bids(&snapshot.bids)?;
+                self.orderbook.update_asks(&snapshot.asks)?;
+
+                let mut last_update_id = snapshot.last_update_id;
+                for event in buffered_events.iter() {
+                    match self.apply_event(last_update_id, event)? {
+                        Some(update_id) => last_update_id = update_id,
+                        None => {
+                            self.resync();
+                            return Ok(false);
                         }
                     }
                 }
 
-                let data = message.into_data();
+                self.sync = BinanceOrderbookSync::Synced { last_update_id };
+            }
+        }
 
-                match serde_json::from_slice::<BinanceApiOrderBookMessage>(&data) {
-                    Ok(orderbook) => {
-                        if tx.is_disconnected() {
-                            println!("Channel is closed. Unsubscribing from Binance API...");
-                            should_reconnect = false;
-                            break;
-                        }
+        Ok(true)
+    }
+}
 
-                        let mut orderbook = orderbook;
-                        if depth < effective_depth {
-                            orderbook.trim(depth);
-                        }
+/// Maintains local orderbooks of any depth, as described in
+/// https://github.com/binance/binance-spot-api-docs/blob/master/web-socket-streams.md#how-to-manage-a-local-order-book-correctly
+pub struct BinanceFullDepthAdapter {
+    url: Url,
+    depth: u16,
+    /// Orderbooks by Binance symbol, like `ethbtc`
+    books: HashMap<String, BinanceBook>,
+}
 
-                        let orderbook_data = ExchangeOrderbookData::from(orderbook);
+impl BinanceFullDepthAdapter {
+    pub fn new(pairs: &[Pair], depth: u16) -> Self {
+        let books = pairs
+            .iter()
+            .map(|pair| {
+                let symbol = pair.exchange_symbol(BINANCE_EXCHANGE_NAME);
+                let book = BinanceBook::new(pair, &symbol);
+                (symbol, book)
+            })
+            .collect();
 
-                        tx.send_async(orderbook_data)
-                            .await
-                            .expect("Failed to send orderbook data from Binance API");
-                    }
-                    Err(_) => match serde_json::from_slice::<BinanceApiErrorMessage>(&data) {
-                        Ok(error_message) => {
-                            println!(
-                                "Error from Binance API: {} (code: {})",
-                                error_message.error.msg, error_message.error.code
-                            );
-                        }
-                        Err(error) => {
-                            println!("Error parsing Binance API message: {}", error);
-                        }
-                    },
-                }
+        Self {
+            url: get_api_url(),
+            depth,
+            books,
+        }
+    }
+}
+
+impl ExchangeAdapter for BinanceFullDepthAdapter {
+    fn name(&self) -> &'static str {
+        BINANCE_EXCHANGE_NAME
+    }
+
+    fn url(&self) -> Url {
+        self.url.clone()
+    }
+
+    fn on_connected(&mut self) {
+        // Events from the previous connection can't be used with the new one
+        for book in self.books.values_mut() {
+            book.resync();
+        }
+    }
+
+    fn subscribe_messages(&self) -> Vec<String> {
+        let streams = self
+            .books
+            .keys()
+            .map(|symbol| format!("{}@depth@100ms", symbol));
+
+        get_subscribe_messages(streams)
+    }
+
+    fn depth_limit(&self) -> Option<u16> {
+        None
+    }
+
+    fn parse_message(
+        &mut self,
+        data: &[u8],
+    ) -> Result<AdapterMessage, Box<dyn Error + Send + Sync>> {
+        let (symbol, event) = match parse_binance_message::<BinanceApiDepthUpdateMessage>(data)? {
+            BinanceMessage::Data(symbol, event) => (symbol, event),
+            BinanceMessage::Other => return Ok(AdapterMessage::Ignore),
+        };
+
+        let book = self
+            .books
+            .get_mut(&symbol)
+            .ok_or_else(|| format!("Unexpected Binance symbol: {}", symbol))?;
+
+        let event_time = event.event_time;
+        if !book.handle_event(event)? {
+            return Ok(AdapterMessage::Ignore);
+        }
+
+        Ok(AdapterMessage::Orderbook(
+            book.pair.clone(),
+            book.orderbook.to_exchange_orderbook_data(
+                BINANCE_EXCHANGE_NAME,
+                self.depth,
+                Some(event_time),
+            ),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn levels(levels: &[(&str, &str)]) -> Vec<(String, String)> {
+        levels
+            .iter()
+            .map(|(price, amount)| (price.to_string(), amount.to_string()))
+            .collect()
+    }
+
+    fn event(
+        first_update_id: u64,
+        final_update_id: u64,
+        bids: &[(&str, &str)],
+    ) -> BinanceApiDepthUpdateMessage {
+        BinanceApiDepthUpdateMessage {
+            event_time: 0,
+            first_update_id,
+            final_update_id,
+            bids: levels(bids),
+            asks: vec![],
+        }
+    }
+
+    fn snapshot(last_update_id: u64, bids: &[(&str, &str)]) -> BinanceApiDepthSnapshot {
+        BinanceApiDepthSnapshot {
+            last_update_id,
+            bids: levels(bids),
+            asks: vec![],
+        }
+    }
+
+    /// Book which waits for the snapshot sent to the returned sender.
+    /// New snapshots are requested from a closed port, so they never arrive.
+    fn new_book() -> (BinanceBook, flume::Sender<BinanceApiDepthSnapshot>) {
+        let (snapshot_tx, snapshot_rx) = flume::bounded(1);
+
+        let mut book = BinanceBook::new(&Pair::parse("ethbtc").unwrap(), "ethbtc");
+        book.snapshot_url = Url::parse("http://127.0.0.1:1/depth").unwrap();
+        book.sync = BinanceOrderbookSync::WaitingForSnapshot {
+            snapshot_rx,
+            buffered_events: vec![],
+        };
+
+        (book, snapshot_tx)
+    }
+
+    fn bids(book: &BinanceBook) -> Vec<String> {
+        book.orderbook
+            .best_bids(10)
+            .iter()
+            .map(|(price, amount)| format!("{} {}", price, amount))
+            .collect()
+    }
+
+    #[test]
+    fn buffers_events_until_snapshot() {
+        let (mut book, snapshot_tx) = new_book();
+
+        assert!(!book.handle_event(event(100, 105, &[("1", "2")])).unwrap());
+        assert!(!book.handle_event(event(106, 110, &[("2", "1")])).unwrap());
+        assert!(book.orderbook.best_bids(10).is_empty());
+
+        // The first event overlaps the snapshot, so it's applied too
+        snapshot_tx
+            .send(snapshot(104, &[("1", "5"), ("3", "1")]))
+            .unwrap();
+        assert!(book.handle_event(event(111, 112, &[("3", "0")])).unwrap());
+
+        assert_eq!(bids(&book), ["2 1", "1 2"]);
+        assert!(matches!(
+            book.sync,
+            BinanceOrderbookSync::Synced {
+                last_update_id: 112
             }
+        ));
+    }
+
+    #[test]
+    fn drops_events_older_than_snapshot() {
+        let (mut book, snapshot_tx) = new_book();
+        snapshot_tx.send(snapshot(100, &[("1", "5")])).unwrap();
+
+        assert!(book.handle_event(event(90, 100, &[("1", "1")])).unwrap());
+        assert_eq!(bids(&book), ["1 5"]);
+
+        // Already applied events are skipped after sync too
+        assert!(book.handle_event(event(95, 100, &[("1", "1")])).unwrap());
+        assert!(book.handle_event(event(101, 101, &[("2", "1")])).unwrap());
+        assert_eq!(bids(&book), ["2 1", "1 5"]);
+    }
 
-            socket
-                .close(None)
-                .expect("Failed to close Binance API connection");
+    #[tokio::test]
+    async fn requests_new_snapshot_if_it_is_older_than_events() {
+        let (mut book, snapshot_tx) = new_book();
+        snapshot_tx.send(snapshot(50, &[("1", "5")])).unwrap();
+
+        assert!(!book.handle_event(event(100, 105, &[("1", "1")])).unwrap());
+        assert!(book.orderbook.best_bids(10).is_empty());
+        match &book.sync {
+            BinanceOrderbookSync::WaitingForSnapshot {
+                buffered_events, ..
+            } => assert_eq!(buffered_events.len(), 1),
+            BinanceOrderbookSync::Synced { .. } => panic!("Synced with outdated snapshot"),
         }
-    })
+    }
+
+    #[tokio::test]
+    async fn resyncs_on_gap() {
+        let (mut book, snapshot_tx) = new_book();
+        snapshot_tx.send(snapshot(100, &[("1", "5")])).unwrap();
+        assert!(book.handle_event(event(100, 102, &[])).unwrap());
+
+        // Update 103 is missed
+        assert!(!book.handle_event(event(104, 105, &[("2", "1")])).unwrap());
+
+        assert!(book.orderbook.best_bids(10).is_empty());
+        match &book.sync {
+            BinanceOrderbookSync::WaitingForSnapshot {
+                buffered_events, ..
+            } => {
+                assert_eq!(buffered_events.len(), 1);
+                assert_eq!(buffered_events[0].first_update_id, 104);
+            }
+            BinanceOrderbookSync::Synced { .. } => panic!("Synced with a gap"),
+        }
+    }
+
+    #[tokio::test]
+    async fn resyncs_if_snapshot_is_not_received_in_time() {
+        let (mut book, _snapshot_tx) = new_book();
+
+        for update_id in 1..=BINANCE_MAX_BUFFERED_EVENTS as u64 {
+            assert!(!book.handle_event(event(update_id, update_id, &[])).unwrap());
+        }
+        let update_id = BINANCE_MAX_BUFFERED_EVENTS as u64 + 1;
+        assert!(!book.handle_event(event(update_id, update_id, &[])).unwrap());
+
+        match &book.sync {
+            BinanceOrderbookSync::WaitingForSnapshot {
+                buffered_events, ..
+            } => assert!(buffered_events.is_empty()),
+            BinanceOrderbookSync::Synced { .. } => panic!("Synced without snapshot"),
+        }
+    }
 }
@@ -0,0 +1,492 @@
+use std::{collections::HashMap, error::Error};
+
+use serde::Deserialize;
+use url::Url;
+
+use super::adapter::{AdapterMessage, ExchangeAdapter};
+use super::local_orderbook::LocalOrderbook;
+use super::symbol::Pair;
+use crate::decimal::Decimal;
+use crate::helpers::get_env_var_or_default;
+
+/// Bitfinex sends either event objects, or arrays prefixed with numeric channel ID
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum BitfinexApiIncomingMessage {
+    Event(BitfinexApiEventMessage),
+    Channel(u64, BitfinexApiChannelData),
+}
+
+#[derive(Deserialize, Debug)]
+struct BitfinexApiEventMessage {
+    /// `info`, `subscribed`, `unsubscribed`, `pong`, `error`, etc.
+    event: String,
+    #[serde(rename = "chanId")]
+    chan_id: Option<u64>,
+    /// Like `tETHBTC`, present in `subscribed` events
+    symbol: Option<String>,
+    code: Option<u32>,
+    msg: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(untagged)]
+enum BitfinexApiChannelData {
+    /// `hb` is sent every 15 seconds if there are no updates
+    Heartbeat(String),
+    Snapshot(Vec<BitfinexApiBookEntry>),
+    Update(BitfinexApiBookEntry),
+}
+
+/// [price, count, amount] for aggregated books,
+/// [order ID, price, amount] for raw books.
+/// Positive amount is a bid, negative amount is an ask.
+type BitfinexApiBookEntry = (Decimal, Decimal, Decimal);
+
+pub const BITFINEX_EXCHANGE_NAME: &str = "bitfinex";
+const DEFAULT_BITFINEX_API_URL: &str = "wss://api-pub.bitfinex.com/ws/2";
+const BITFINEX_SUPPORTED_DEPTH_LIMITS: [u16; 4] = [1, 25, 100, 250];
+const BITFINEX_PING_MESSAGE: &str = r#"{"event": "ping", "cid": 1}"#;
+// Info codes: server is going to restart, and end of maintenance
+const BITFINEX_INFO_CODE_RECONNECT: u32 = 20051;
+const BITFINEX_INFO_CODE_MAINTENANCE_END: u32 = 20061;
+
+pub fn new_adapter(pairs: &[Pair], depth: u16) -> Box<dyn ExchangeAdapter> {
+    Box::new(BitfinexAdapter::new(pairs, depth))
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum BitfinexBookPrecision {
+    /// `P0`: price levels with number of orders
+    Aggregated,
+    /// `R0`: individual orders, which are aggregated to price levels locally
+    Raw,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct BitfinexRawOrder {
+    price: Decimal,
+    /// Positive for bids, negative for asks
+    amount: Decimal,
+}
+
+/// Local orderbook of a single pair
+struct BitfinexBook {
+    pair: Pair,
+    orderbook: LocalOrderbook,
+    /// Orders of raw book by order ID
+    raw_orders: HashMap<Decimal, BitfinexRawOrder>,
+    /// Whether snapshot is received after subscription
+    is_synced: bool,
+}
+
+impl BitfinexBook {
+    fn new(pair: &Pair) -> Self {
+        Self {
+            pair: pair.clone(),
+            orderbook: LocalOrderbook::default(),
+            raw_orders: HashMap::new(),
+            is_synced: false,
+        }
+    }
+
+    fn reset(&mut self) {
+        self.orderbook.clear();
+        self.raw_orders.clear();
+        self.is_synced = false;
+    }
+
+    fn apply_entry(&mut self, entry: BitfinexApiBookEntry, precision: BitfinexBookPrecision) {
+        match precision {
+            BitfinexBookPrecision::Aggregated => {
+                let (price, count, amount) = entry;
+                // Zero count removes the level, amount is 1 for bids and -1 for asks then
+                let level_amount = if count.is_zero() {
+                    Decimal::ZERO
+                } else {
+                    amount.abs()
+                };
+
+                if amount.is_positive() {
+                    self.orderbook.update_bid(price, level_amount);
+                } else {
+                    self.orderbook.update_ask(price, level_amount);
+                }
+            }
+            BitfinexBookPrecision::Raw => {
+                let (order_id, price, amount) = entry;
+
+                // Zero price removes the order
+                let previous_order = if price.is_zero() {
+                    self.raw_orders.remove(&order_id)
+                } else {
+                    self.raw_orders
+                        .insert(order_id, BitfinexRawOrder { price, amount })
+                };
+
+                if let Some(previous_order) = previous_order {
+                    self.update_raw_level(
+                        previous_order.price,
+                        previous_order.amount.is_positive(),
+                    );
+                }
+                if !price.is_zero() {
+                    self.update_raw_level(price, amount.is_positive());
+                }
+            }
+        }
+    }
+
+    /// Recalculates price level of raw book from its orders
+    fn update_raw_level(&mut self, price: Decimal, is_bid: bool) {
+        let amount: Decimal = self
+            .raw_orders
+            .values()
+            .filter(|order| order.price == price && order.amount.is_positive() == is_bid)
+            .map(|order| order.amount.abs())
+            .sum();
+
+        if is_bid {
+            self.orderbook.update_bid(price, amount);
+        } else {
+            self.orderbook.update_ask(price, amount);
+        }
+    }
+}
+
+/// Book channels with `P0` precision, or raw `R0` books if `BITFINEX_RAW_BOOKS` is set
+/// Docs: https://docs.bitfinex.com/reference/ws-public-books
+/// and https://docs.bitfinex.com/reference/ws-public-raw-books
+pub struct BitfinexAdapter {
+    url: Url,
+    /// Pairs by Bitfinex trading pair symbol, like `tETHBTC`
+    pairs: HashMap<String, Pair>,
+    precision: BitfinexBookPrecision,
+    /// Length of subscription, one of `BITFINEX_SUPPORTED_DEPTH_LIMITS`.
+    /// It's number of price levels for aggregated books, and number of orders for raw books.
+    subscription_depth: u16,
+    depth: u16,
+    /// Orderbooks by channel ID, which is assigned by Bitfinex on subscription
+    books: HashMap<u64, BitfinexBook>,
+}
+
+impl BitfinexAdapter {
+    pub fn new(pairs: &[Pair], depth: u16) -> Self {
+        let url = get_env_var_or_default("BITFINEX_API_URL", DEFAULT_BITFINEX_API_URL.to_string());
+        let url = Url::parse(&url).expect("Failed to parse Bitfinex API URL");
+
+        let precision = if get_env_var_or_default("BITFINEX_RAW_BOOKS", false) {
+            BitfinexBookPrecision::Raw
+        } else {
+            BitfinexBookPrecision::Aggregated
+        };
+
+        let subscription_depth = BITFINEX_SUPPORTED_DEPTH_LIMITS
+            .iter()
+            .find(|&&limit| limit >= depth)
+            .unwrap_or(&BITFINEX_SUPPORTED_DEPTH_LIMITS[3])
+            .to_owned();
+
+        let pairs = pairs
+            .iter()
+            .map(|pair| (pair.exchange_symbol(BITFINEX_EXCHANGE_NAME), pair.clone()))
+            .collect();
+
+        Self {
+            url,
+            pairs,
+            precision,
+            subscription_depth,
+            depth,
+            books: HashMap::new(),
+        }
+    }
+
+    fn handle_event_message(&mut self, message: BitfinexApiEventMessage) -> AdapterMessage {
+        match message.event.as_str() {
+            "subscribed" => {
+                let pair = message
+                    .symbol
+                    .and_then(|symbol| self.pairs.get(&symbol).cloned());
+
+                if let (Some(chan_id), Some(pair)) = (message.chan_id, pair) {
+                    self.books.insert(chan_id, BitfinexBook::new(&pair));
+                }
+                AdapterMessage::Ignore
+            }
+            "unsubscribed" => {
+                if let Some(chan_id) = message.chan_id {
+                    self.books.remove(&chan_id);
+                }
+                AdapterMessage::Ignore
+            }
+            "info" => match message.code {
+                Some(BITFINEX_INFO_CODE_RECONNECT) => AdapterMessage::Reconnect,
+                Some(BITFINEX_INFO_CODE_MAINTENANCE_END) => {
+                    for book in self.books.values_mut() {
+                        book.reset();
+                    }
+                    AdapterMessage::Resubscribe
+                }
+                _ => AdapterMessage::Ignore,
+            },
+            "error" => {
+                println!(
+                    "Error from Bitfinex API: {} (code: {})",
+                    message.msg.unwrap_or_default(),
+                    message.code.unwrap_or_default()
+                );
+                AdapterMessage::Ignore
+            }
+            _ => AdapterMessage::Ignore,
+        }
+    }
+
+    fn handle_channel_message(
+        &mut self,
+        chan_id: u64,
+        data: BitfinexApiChannelData,
+    ) -> AdapterMessage {
+        // Messages of unknown channels, like the ones which are already unsubscribed
+        let book = match self.books.get_mut(&chan_id) {
+            Some(book) => book,
+            None => return AdapterMessage::Ignore,
+        };
+
+        match data {
+            BitfinexApiChannelData::Snapshot(entries) => {
+                book.reset();
+                book.is_synced = true;
+
+                for entry in entries {
+                    book.apply_entry(entry, self.precision);
+                }
+            }
+            BitfinexApiChannelData::Update(entry) => {
+                if !book.is_synced {
+                    return AdapterMessage::Ignore;
+                }
+                book.apply_entry(entry, self.precision);
+            }
+            // Heartbeat means there are no updates, so the orderbook is still actual
+            BitfinexApiChannelData::Heartbeat(message) => {
+                if message != "hb" || !book.is_synced {
+                    return AdapterMessage::Ignore;
+                }
+            }
+        }
+
+        if self.precision == BitfinexBookPrecision::Aggregated {
+            // Levels which are out of subscription depth are not updated anymore
+            book.orderbook.truncate(self.subscription_depth);
+        }
+
+        // Book messages have no exchange time, so receive time is reported as exchange time
+        AdapterMessage::Orderbook(
+            book.pair.clone(),
+            book.orderbook
+                .to_exchange_orderbook_data(BITFINEX_EXCHANGE_NAME, self.depth, None),
+        )
+    }
+}
+
+impl ExchangeAdapter for BitfinexAdapter {
+    fn name(&self) -> &'static str {
+        BITFINEX_EXCHANGE_NAME
+    }
+
+    fn url(&self) -> Url {
+        self.url.clone()
+    }
+
+    fn on_connected(&mut self) {
+        // Channel IDs are assigned per connection
+        self.books.clear();
+    }
+
+    fn subscribe_messages(&self) -> Vec<String> {
+        let precision = match self.precision {
+            BitfinexBookPrecision::Aggregated => "P0",
+            BitfinexBookPrecision::Raw => "R0",
+        };
+
+        self.pairs
+            .keys()
+            .map(|symbol| {
+                format!(
+                    r#"{{"event": "subscribe", "channel": "book", "symbol": "{}", "prec": "{}", "len": "{}"}}"#,
+                    symbol, precision, self.subscription_depth
+                )
+            })
+            .collect()
+    }
+
+    fn unsubscribe_messages(&self) -> Vec<String> {
+        self.books
+            .keys()
+            .map(|chan_id| format!(r#"{{"event": "unsubscribe", "chanId": {}}}"#, chan_id))
+            .collect()
+    }
+
+    fn ping_message(&self) -> Option<String> {
+        Some(BITFINEX_PING_MESSAGE.to_string())
+    }
+
+    fn depth_limit(&self) -> Option<u16> {
+        Some(BITFINEX_SUPPORTED_DEPTH_LIMITS[3])
+    }
+
+    fn parse_message(
+        &mut self,
+        data: &[u8],
+    ) -> Result<AdapterMessage, Box<dyn Error + Send + Sync>> {
+        let message: BitfinexApiIncomingMessage = serde_json::from_slice(data)?;
+
+        match message {
+            BitfinexApiIncomingMessage::Event(event) => Ok(self.handle_event_message(event)),
+            BitfinexApiIncomingMessage::Channel(chan_id, data) => {
+                Ok(self.handle_channel_message(chan_id, data))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn subscribed_adapter(precision: BitfinexBookPrecision) -> BitfinexAdapter {
+        let pairs = [
+            Pair::parse("ethbtc").unwrap(),
+            Pair::parse("ltcbtc").unwrap(),
+        ];
+        let mut adapter = BitfinexAdapter::new(&pairs, 10);
+        adapter.precision = precision;
+
+        for (chan_id, symbol) in [(10, "tETHBTC"), (20, "tLTCBTC")] {
+            let message = format!(
+                r#"{{"event": "subscribed", "channel": "book", "chanId": {}, "symbol": "{}"}}"#,
+                chan_id, symbol
+            );
+            parse(&mut adapter, &message);
+        }
+
+        adapter
+    }
+
+    fn parse(adapter: &mut BitfinexAdapter, message: &str) -> AdapterMessage {
+        adapter.parse_message(message.as_bytes()).unwrap()
+    }
+
+    /// Pair and levels of orderbook message, like `0.049 2`
+    fn levels(message: AdapterMessage) -> (Pair, Vec<String>, Vec<String>) {
+        let format = |levels: Vec<(Decimal, Decimal)>| -> Vec<String> {
+            levels
+                .iter()
+                .map(|(price, amount)| format!("{} {}", price, amount))
+                .collect()
+        };
+
+        match message {
+            AdapterMessage::Orderbook(pair, data) => (pair, format(data.bids), format(data.asks)),
+            _ => panic!("Expected orderbook"),
+        }
+    }
+
+    #[test]
+    fn routes_channels_by_subscription() {
+        let mut adapter = subscribed_adapter(BitfinexBookPrecision::Aggregated);
+
+        // Updates before snapshot and messages of unknown channels are skipped
+        assert!(matches!(
+            parse(&mut adapter, "[20, [0.002, 1, 3]]"),
+            AdapterMessage::Ignore
+        ));
+        assert!(matches!(
+            parse(&mut adapter, "[30, [[0.002, 1, 3]]]"),
+            AdapterMessage::Ignore
+        ));
+
+        let (pair, bids, _) = levels(parse(&mut adapter, "[20, [[0.002, 1, 3]]]"));
+        assert_eq!(pair, Pair::parse("ltcbtc").unwrap());
+        assert_eq!(bids, ["0.002 3"]);
+
+        let (pair, bids, _) = levels(parse(&mut adapter, "[10, [[0.049, 1, 2]]]"));
+        assert_eq!(pair, Pair::parse("ethbtc").unwrap());
+        assert_eq!(bids, ["0.049 2"]);
+    }
+
+    #[test]
+    fn removes_aggregated_levels_with_zero_count() {
+        let mut adapter = subscribed_adapter(BitfinexBookPrecision::Aggregated);
+        let (_, bids, asks) = levels(parse(
+            &mut adapter,
+            "[10, [[0.049, 1, 2], [0.048, 3, 1.5], [0.0505, 2, -1]]]",
+        ));
+        assert_eq!(bids, ["0.049 2", "0.048 1.5"]);
+        assert_eq!(asks, ["0.0505 1"]);
+
+        let (_, bids, _) = levels(parse(&mut adapter, "[10, [0.049, 0, 1]]"));
+        assert_eq!(bids, ["0.048 1.5"]);
+
+        let (_, _, asks) = levels(parse(&mut adapter, "[10, [0.0505, 0, -1]]"));
+        assert!(asks.is_empty());
+    }
+
+    #[test]
+    fn aggregates_raw_orders_by_price() {
+        let mut adapter = subscribed_adapter(BitfinexBookPrecision::Raw);
+        let (_, bids, asks) = levels(parse(
+            &mut adapter,
+            "[10, [[1, 0.049, 1], [2, 0.049, 0.5], [3, 0.0505, -1]]]",
+        ));
+        assert_eq!(bids, ["0.049 1.5"]);
+        assert_eq!(asks, ["0.0505 1"]);
+
+        // Order moves to another price
+        let (_, bids, _) = levels(parse(&mut adapter, "[10, [2, 0.048, 0.5]]"));
+        assert_eq!(bids, ["0.049 1", "0.048 0.5"]);
+
+        // Order changes side at the same price
+        let (_, bids, asks) = levels(parse(&mut adapter, "[10, [1, 0.049, -2]]"));
+        assert_eq!(bids, ["0.048 0.5"]);
+        assert_eq!(asks, ["0.049 2", "0.0505 1"]);
+
+        // Zero price removes the order
+        let (_, _, asks) = levels(parse(&mut adapter, "[10, [3, 0, -1]]"));
+        assert_eq!(asks, ["0.049 2"]);
+    }
+
+    #[test]
+    fn sends_orderbook_on_heartbeat_after_snapshot() {
+        let mut adapter = subscribed_adapter(BitfinexBookPrecision::Aggregated);
+        assert!(matches!(
+            parse(&mut adapter, r#"[10, "hb"]"#),
+            AdapterMessage::Ignore
+        ));
+
+        parse(&mut adapter, "[10, [[0.049, 1, 2]]]");
+        let (_, bids, _) = levels(parse(&mut adapter, r#"[10, "hb"]"#));
+        assert_eq!(bids, ["0.049 2"]);
+    }
+
+    #[test]
+    fn handles_info_events() {
+        let mut adapter = subscribed_adapter(BitfinexBookPrecision::Aggregated);
+        parse(&mut adapter, "[10, [[0.049, 1, 2]]]");
+
+        assert!(matches!(
+            parse(&mut adapter, r#"{"event": "info", "code": 20051}"#),
+            AdapterMessage::Reconnect
+        ));
+        assert!(matches!(
+            parse(&mut adapter, r#"{"event": "info", "code": 20061}"#),
+            AdapterMessage::Resubscribe
+        ));
+        // Books are rebuilt from new snapshots after maintenance
+        assert!(matches!(
+            parse(&mut adapter, "[10, [0.048, 1, 1]]"),
+            AdapterMessage::Ignore
+        ));
+    }
+}
@@ -1,31 +1,28 @@
+use std::{collections::HashMap, error::Error};
+
 use serde::Deserialize;
-use tungstenite::{connect, Message};
 use url::Url;
 
-use super::output_data_format::ExchangeOrderbookData;
+use super::adapter::{AdapterMessage, ExchangeAdapter};
+use super::local_orderbook::LocalOrderbook;
+use super::snapshot::request_snapshot;
+use super::symbol::Pair;
 use crate::helpers::get_env_var_or_default;
 
 #[derive(Deserialize, Debug)]
 pub struct BitstampApiOrderBookData {
-    /// timestamp in seconds
-    pub timestamp: String,
-    // microtimestamp: String,
+    /// timestamp in microseconds
+    pub microtimestamp: String,
     pub bids: Vec<(String, String)>,
     pub asks: Vec<(String, String)>,
 }
 
-impl BitstampApiOrderBookData {
-    pub fn trim(&mut self, depth: u16) {
-        self.bids.truncate(depth as usize);
-        self.asks.truncate(depth as usize);
-    }
-}
-
 #[derive(Deserialize, Debug)]
 struct BitstampApiIncomingMessage {
     event: String,
-    // it also contains `channel` field, but it's not used in this app.
-
+    /// Empty for messages which are not related to a channel, like heartbeat
+    #[serde(default)]
+    channel: String,
     // it also has `data` field, but it's not always present and it's not always the same type
     // so we use `BitstampApiOrderBookDataMessage` or `BitstampApiErrorMessage` depending on `event` field value
 }
@@ -46,140 +43,542 @@ struct BitstampApiErrorMessage {
     data: BitstampApiErrorData,
 }
 
+pub const BITSTAMP_EXCHANGE_NAME: &str = "bitstamp";
 const DEFAULT_BITSTAMP_API_URL: &str = "wss://ws.bitstamp.net";
+const DEFAULT_BITSTAMP_REST_API_URL: &str = "https://www.bitstamp.net/api/v2";
 const BITSTAMP_DEPTH_LIMIT: u16 = 100;
 const BITSTAMP_EVENT_SUBSCRIBE: &str = "bts:subscribe";
+const BITSTAMP_EVENT_HEARTBEAT: &str = "bts:heartbeat";
 const BITSTAMP_ORDERBOOK_CHANNEL_PREFIX: &str = "order_book_";
+const BITSTAMP_DIFF_ORDERBOOK_CHANNEL_PREFIX: &str = "diff_order_book_";
+// If snapshot takes longer to arrive, the orderbook is resynced
+const BITSTAMP_MAX_BUFFERED_DIFFS: usize = 10_000;
 
 // Maximal age of connection is 90 days
 const BITSTAMP_CONNECTION_AGE_LIMIT_SECONDS: u64 = 90 * 24 * 60 * 60;
-const BITSTAMP_RECONNECTION_FREQUENCY_SECONDS: u64 = BITSTAMP_CONNECTION_AGE_LIMIT_SECONDS - 60;
 
-pub fn spawn_thread(
-    symbol: String,
-    depth: u16,
-    tx: flume::Sender<ExchangeOrderbookData>,
-) -> tokio::task::JoinHandle<()> {
-    if depth > BITSTAMP_DEPTH_LIMIT {
-        println!(
-            "[WARNING] Bitstamp API supports only depth <= {}. Only first {} levels will be used.",
-            BITSTAMP_DEPTH_LIMIT, BITSTAMP_DEPTH_LIMIT
-        );
-        // "Unlimited" depth support may be implemented with requesting initial orderbook and then using `diff_order_book` channel
-        // Docs: https://www.bitstamp.net/websocket/v2/
-        // But it's not the goal of this app.
+/// Channel with top 100 levels if `BITSTAMP_FULL_DEPTH` is not set,
+/// otherwise local orderbook maintained from `diff_order_book` channel
+pub fn new_adapter(pairs: &[Pair], depth: u16) -> Box<dyn ExchangeAdapter> {
+    let full_depth = get_env_var_or_default("BITSTAMP_FULL_DEPTH", false);
+
+    if full_depth {
+        Box::new(BitstampFullDepthAdapter::new(pairs, depth))
+    } else {
+        Box::new(BitstampAdapter::new(pairs, depth))
     }
+}
 
+fn get_api_url() -> Url {
     let url = get_env_var_or_default("BITSTAMP_API_URL", DEFAULT_BITSTAMP_API_URL.to_string());
-    let url = Url::parse(&url).expect("Failed to parse Bitstamp API URL");
+    Url::parse(&url).expect("Failed to parse Bitstamp API URL")
+}
 
-    let channel = format!("{}{}", BITSTAMP_ORDERBOOK_CHANNEL_PREFIX, symbol);
-    let subscribe_message = format!(
+fn get_subscribe_message(channel: &str) -> String {
+    format!(
         r#"{{"event": "{}", "data": {{"channel": "{}"}}}}"#,
         BITSTAMP_EVENT_SUBSCRIBE, channel
-    );
+    )
+}
 
-    tokio::spawn(async move {
-        let mut should_reconnect = true;
+fn get_ping_message() -> String {
+    format!(r#"{{"event": "{}"}}"#, BITSTAMP_EVENT_HEARTBEAT)
+}
 
-        while should_reconnect {
-            let (mut socket, _) = connect(url.clone()).expect("Can't connect to Bitstamp API");
+/// Incoming message, common for all channels
+enum BitstampMessage {
+    /// Channel name and its data
+    Data(String, BitstampApiOrderBookData),
+    Reconnect,
+    Other,
+}
 
-            socket
-                .write_message(Message::Text(subscribe_message.clone()))
-                .expect("Error writing subscribe message");
+fn parse_bitstamp_message(data: &[u8]) -> Result<BitstampMessage, Box<dyn Error + Send + Sync>> {
+    let response: BitstampApiIncomingMessage = serde_json::from_slice(data)?;
 
-            let connection_time = std::time::Instant::now();
+    if response.event == "bts:request_reconnect" {
+        return Ok(BitstampMessage::Reconnect);
+    }
 
-            loop {
-                if !socket.can_read() {
-                    println!("Bitstamp API connection is closed by server. Reconnecting...");
-                    should_reconnect = true;
-                    break;
-                }
+    if response.event == "bts:error" {
+        match serde_json::from_slice::<BitstampApiErrorMessage>(data) {
+            Ok(bitstamp_error_message) => {
+                let data = bitstamp_error_message.data;
+                let message = data.message;
+                let code = data.code.unwrap_or(-1);
+                println!("Error from Bitstamp API: {} (code: {})", message, code);
+            }
+            Err(error) => {
+                println!("Error parsing Bitstamp API error message: {}", error);
+            }
+        }
+
+        return Ok(BitstampMessage::Other);
+    }
+
+    if response.event == "data" {
+        let orderbook_data_message: BitstampApiOrderBookDataMessage = serde_json::from_slice(data)?;
+
+        return Ok(BitstampMessage::Data(
+            response.channel,
+            orderbook_data_message.data,
+        ));
+    }
+
+    Ok(BitstampMessage::Other)
+}
+
+/// Uses `order_book` channels, so only top 100 levels are available
+pub struct BitstampAdapter {
+    url: Url,
+    /// Pairs by channel name, like `order_book_ethbtc`
+    channels: HashMap<String, Pair>,
+}
+
+impl BitstampAdapter {
+    pub fn new(pairs: &[Pair], _depth: u16) -> Self {
+        // For "unlimited" depth see `BitstampFullDepthAdapter`
+
+        let url = get_api_url();
+        let channels = pairs
+            .iter()
+            .map(|pair| {
+                let symbol = pair.exchange_symbol(BITSTAMP_EXCHANGE_NAME);
+                let channel = format!("{}{}", BITSTAMP_ORDERBOOK_CHANNEL_PREFIX, symbol);
+                (channel, pair.clone())
+            })
+            .collect();
+
+        Self { url, channels }
+    }
+}
+
+impl ExchangeAdapter for BitstampAdapter {
+    fn name(&self) -> &'static str {
+        BITSTAMP_EXCHANGE_NAME
+    }
+
+    fn url(&self) -> Url {
+        self.url.clone()
+    }
 
-                if connection_time.elapsed().as_secs() > BITSTAMP_RECONNECTION_FREQUENCY_SECONDS {
-                    println!("Bitstamp API connection is too old. Reconnecting...");
-                    should_reconnect = true;
-                    break;
+    fn subscribe_messages(&self) -> Vec<String> {
+        self.channels
+            .keys()
+            .map(|channel| get_subscribe_message(channel))
+            .collect()
+    }
+
+    fn ping_message(&self) -> Option<String> {
+        Some(get_ping_message())
+    }
+
+    fn connection_age_limit_seconds(&self) -> u64 {
+        BITSTAMP_CONNECTION_AGE_LIMIT_SECONDS
+    }
+
+    fn depth_limit(&self) -> Option<u16> {
+        Some(BITSTAMP_DEPTH_LIMIT)
+    }
+
+    fn parse_message(
+        &mut self,
+        data: &[u8],
+    ) -> Result<AdapterMessage, Box<dyn Error + Send + Sync>> {
+        match parse_bitstamp_message(data)? {
+            BitstampMessage::Data(channel, orderbook_data) => {
+                let pair = self
+                    .channels
+                    .get(&channel)
+                    .ok_or_else(|| format!("Unexpected Bitstamp channel: {}", channel))?;
+
+                Ok(AdapterMessage::Orderbook(
+                    pair.clone(),
+                    orderbook_data.try_into()?,
+                ))
+            }
+            BitstampMessage::Reconnect => Ok(AdapterMessage::Reconnect),
+            BitstampMessage::Other => Ok(AdapterMessage::Ignore),
+        }
+    }
+}
+
+/// Synchronization of local orderbook with `diff_order_book` channel
+enum BitstampOrderbookSync {
+    /// Diffs are buffered until REST API snapshot arrives. Snapshot is requested only after
+    /// the first diff is buffered, so that it overlaps with the buffered diffs.
+    WaitingForSnapshot {
+        snapshot_rx: Option<flume::Receiver<BitstampApiOrderBookData>>,
+        buffered_diffs: Vec<BitstampApiOrderBookData>,
+    },
+    /// Local orderbook contains all diffs up to `last_microtimestamp`
+    Synced { last_microtimestamp: u64 },
+}
+
+/// Local orderbook of a single pair
+struct BitstampBook {
+    pair: Pair,
+    snapshot_url: Url,
+    orderbook: LocalOrderbook,
+    sync: BitstampOrderbookSync,
+}
+
+impl BitstampBook {
+    fn new(pair: &Pair, symbol: &str) -> Self {
+        let rest_url = get_env_var_or_default(
+            "BITSTAMP_REST_API_URL",
+            DEFAULT_BITSTAMP_REST_API_URL.to_string(),
+        );
+        let snapshot_url = format!("{}/order_book/{}/", rest_url, symbol);
+        let snapshot_url =
+            Url::parse(&snapshot_url).expect("Failed to parse Bitstamp REST API URL");
+
+        let sync = BitstampOrderbookSync::WaitingForSnapshot {
+            snapshot_rx: None,
+            buffered_diffs: vec![],
+        };
+
+        Self {
+            pair: pair.clone(),
+            snapshot_url,
+            orderbook: LocalOrderbook::default(),
+            sync,
+        }
+    }
+
+    /// Drops local orderbook. A new snapshot is requested with the next diff,
+    /// and diffs received until it arrives are buffered.
+    fn resync(&mut self) {
+        self.orderbook.clear();
+        self.sync = BitstampOrderbookSync::WaitingForSnapshot {
+            snapshot_rx: None,
+            buffered_diffs: vec![],
+        };
+    }
+
+    /// Applies diff on top of the orderbook which contains all changes up to `last_microtimestamp`.
+    /// Returns new last microtimestamp.
+    fn apply_diff(
+        &mut self,
+        last_microtimestamp: u64,
+        diff: &BitstampApiOrderBookData,
+    ) -> Result<u64, Box<dyn Error + Send + Sync>> {
+        let microtimestamp: u64 = diff.microtimestamp.parse()?;
+
+        if microtimestamp <= last_microtimestamp {
+            // Diff is already included in the orderbook
+            return Ok(last_microtimestamp);
+        }
+
+        self.orderbook.update_bids(&diff.bids)?;
+        self.orderbook.update_asks(&diff.asks)?;
+
+        Ok(microtimestamp)
+    }
+
+    /// Returns the last applied microtimestamp, or `None` if the orderbook is not synced yet
+    fn handle_diff(
+        &mut self,
+        diff: BitstampApiOrderBookData,
+    ) -> Result<Option<u64>, Box<dyn Error + Send + Sync>> {
+        match &mut self.sync {
+            BitstampOrderbookSync::Synced {
+                last_microtimestamp,
+            } => {
+                let last_microtimestamp = *last_microtimestamp;
+                let last_microtimestamp = self.apply_diff(last_microtimestamp, &diff)?;
+                self.sync = BitstampOrderbookSync::Synced {
+                    last_microtimestamp,
+                };
+            }
+            BitstampOrderbookSync::WaitingForSnapshot {
+                snapshot_rx,
+                buffered_diffs,
+            } => {
+                buffered_diffs.push(diff);
+
+                if buffered_diffs.len() > BITSTAMP_MAX_BUFFERED_DIFFS {
+                    println!(
+                        "Bitstamp {} orderbook snapshot is not received in time. Resyncing...",
+                        self.pair
+                    );
+                    self.resync();
+                    return Ok(None);
                 }
 
-                let message = socket
-                    .read_message()
-                    .expect("Error reading message from Bitstamp API");
-
-                if message.is_ping() {
-                    match socket.write_message(Message::Pong(message.into_data())) {
-                        Ok(_) => {
-                            continue;
-                        }
-                        Err(error) => {
-                            println!(
-                                "Error sending PONG message to Bitstamp API: {}. Reconnecting...",
-                                error
-                            );
-                            // if we don't send PONG, the connection will be closed by server immediately
-                            should_reconnect = true;
-                            break;
-                        }
+                let snapshot = match snapshot_rx {
+                    Some(snapshot_rx) => match snapshot_rx.try_recv() {
+                        Ok(snapshot) => snapshot,
+                        Err(_) => return Ok(None),
+                    },
+                    None => {
+                        *snapshot_rx = Some(request_snapshot(
+                            BITSTAMP_EXCHANGE_NAME,
+                            self.snapshot_url.clone(),
+                        ));
+                        return Ok(None);
                     }
+                };
+
+                let snapshot_microtimestamp: u64 = snapshot.microtimestamp.parse()?;
+                let first_diff_microtimestamp: u64 = buffered_diffs[0].microtimestamp.parse()?;
+                if snapshot_microtimestamp < first_diff_microtimestamp {
+                    // Changes between the snapshot and the first buffered diff would be lost,
+                    // and without sequence numbers it's never detected later
+                    *snapshot_rx = Some(request_snapshot(
+                        BITSTAMP_EXCHANGE_NAME,
+                        self.snapshot_url.clone(),
+                    ));
+                    return Ok(None);
                 }
 
-                let data = message.into_data();
-                let response: BitstampApiIncomingMessage = serde_json::from_slice(&data)
-                    .expect("Error parsing BitstampApiIncomingMessage JSON");
+                let buffered_diffs = std::mem::take(buffered_diffs);
+
+                self.orderbook.clear();
+                self.orderbook.update_bids(&snapshot.bids)?;
+                self.orderbook.update_asks(&snapshot.asks)?;
 
-                if response.event == "bts:request_reconnect" {
-                    println!("Bitstamp API requested reconnect. Reconnecting...");
-                    should_reconnect = true;
-                    break;
+                // Diffs older than the snapshot are skipped, newer ones are applied in order
+                let mut last_microtimestamp = snapshot_microtimestamp;
+                for diff in buffered_diffs.iter() {
+                    last_microtimestamp = self.apply_diff(last_microtimestamp, diff)?;
                 }
 
-                if response.event == "bts:error" {
-                    match serde_json::from_slice::<BitstampApiErrorMessage>(&data) {
-                        Ok(bitstamp_error_message) => {
-                            let data = bitstamp_error_message.data;
-                            let message = data.message;
-                            let code = data.code.unwrap_or(-1);
-                            println!("Error from Bitstamp API: {} (code: {})", message, code);
-                        }
-                        Err(error) => {
-                            println!("Error parsing Bitstamp API error message: {}", error);
-                        }
-                    }
+                self.sync = BitstampOrderbookSync::Synced {
+                    last_microtimestamp,
+                };
+            }
+        }
 
-                    continue;
-                }
+        match self.sync {
+            BitstampOrderbookSync::Synced {
+                last_microtimestamp,
+            } => Ok(Some(last_microtimestamp)),
+            BitstampOrderbookSync::WaitingForSnapshot { .. } => Ok(None),
+        }
+    }
+}
 
-                // It would make sense to check if response.channel == channel, but we don't need it in this app, because we only subscribe to one channel
-                if response.event == "data" {
-                    if tx.is_disconnected() {
-                        println!("Channel is closed. Unsubscribing from Bitstamp API...");
-                        should_reconnect = false;
-                        break;
-                    }
+/// Maintains local orderbooks of any depth from REST API snapshots and `diff_order_book` channels.
+/// Bitstamp diffs have no sequence numbers, so `microtimestamp` is used to order them.
+/// Docs: https://www.bitstamp.net/websocket/v2/
+pub struct BitstampFullDepthAdapter {
+    url: Url,
+    depth: u16,
+    /// Orderbooks by channel name, like `diff_order_book_ethbtc`
+    books: HashMap<String, BitstampBook>,
+}
 
-                    let orderbook_data_message: BitstampApiOrderBookDataMessage =
-                        serde_json::from_slice(&data)
-                            .expect("Error parsing BitstampApiOrderBookDataMessage JSON");
+impl BitstampFullDepthAdapter {
+    pub fn new(pairs: &[Pair], depth: u16) -> Self {
+        let url = get_api_url();
+        let books = pairs
+            .iter()
+            .map(|pair| {
+                let symbol = pair.exchange_symbol(BITSTAMP_EXCHANGE_NAME);
+                let channel = format!("{}{}", BITSTAMP_DIFF_ORDERBOOK_CHANNEL_PREFIX, symbol);
+                (channel, BitstampBook::new(pair, &symbol))
+            })
+            .collect();
 
-                    let mut orderbook_data = orderbook_data_message.data;
-                    if depth < BITSTAMP_DEPTH_LIMIT {
-                        // It will reduce further processing time
-                        orderbook_data.trim(depth);
-                    }
+        Self { url, depth, books }
+    }
+}
 
-                    let orderbook_data = ExchangeOrderbookData::from(orderbook_data);
+impl ExchangeAdapter for BitstampFullDepthAdapter {
+    fn name(&self) -> &'static str {
+        BITSTAMP_EXCHANGE_NAME
+    }
 
-                    tx.send_async(orderbook_data)
-                        .await
-                        .expect("Failed to send orderbook data from Bitstamp API");
-                }
+    fn url(&self) -> Url {
+        self.url.clone()
+    }
+
+    fn on_connected(&mut self) {
+        // Diffs may be missed while reconnecting, so orderbooks are rebuilt from new snapshots
+        for book in self.books.values_mut() {
+            book.resync();
+        }
+    }
+
+    fn subscribe_messages(&self) -> Vec<String> {
+        self.books
+            .keys()
+            .map(|channel| get_subscribe_message(channel))
+            .collect()
+    }
+
+    fn ping_message(&self) -> Option<String> {
+        Some(get_ping_message())
+    }
+
+    fn connection_age_limit_seconds(&self) -> u64 {
+        BITSTAMP_CONNECTION_AGE_LIMIT_SECONDS
+    }
+
+    fn depth_limit(&self) -> Option<u16> {
+        None
+    }
+
+    fn parse_message(
+        &mut self,
+        data: &[u8],
+    ) -> Result<AdapterMessage, Box<dyn Error + Send + Sync>> {
+        match parse_bitstamp_message(data)? {
+            BitstampMessage::Data(channel, diff) => {
+                let book = self
+                    .books
+                    .get_mut(&channel)
+                    .ok_or_else(|| format!("Unexpected Bitstamp channel: {}", channel))?;
+
+                let last_microtimestamp = match book.handle_diff(diff)? {
+                    Some(last_microtimestamp) => last_microtimestamp,
+                    None => return Ok(AdapterMessage::Ignore),
+                };
+
+                Ok(AdapterMessage::Orderbook(
+                    book.pair.clone(),
+                    book.orderbook.to_exchange_orderbook_data(
+                        BITSTAMP_EXCHANGE_NAME,
+                        self.depth,
+                        Some(last_microtimestamp / 1000),
+                    ),
+                ))
             }
+            BitstampMessage::Reconnect => Ok(AdapterMessage::Reconnect),
+            BitstampMessage::Other => Ok(AdapterMessage::Ignore),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-            socket
-                .close(None)
-                .expect("Error closing connection to Bitstamp API");
+    fn diff(microtimestamp: u64, bids: &[(&str, &str)]) -> BitstampApiOrderBookData {
+        BitstampApiOrderBookData {
+            microtimestamp: microtimestamp.to_string(),
+            bids: bids
+                .iter()
+                .map(|(price, amount)| (price.to_string(), amount.to_string()))
+                .collect(),
+            asks: vec![],
         }
-    })
+    }
+
+    /// Book which waits for the snapshot sent to the returned sender.
+    /// New snapshots are requested from a closed port, so they never arrive.
+    fn new_book() -> (BitstampBook, flume::Sender<BitstampApiOrderBookData>) {
+        let (snapshot_tx, snapshot_rx) = flume::bounded(1);
+
+        let mut book = BitstampBook::new(&Pair::parse("ethbtc").unwrap(), "ethbtc");
+        book.snapshot_url = Url::parse("http://127.0.0.1:1/order_book/ethbtc/").unwrap();
+        book.sync = BitstampOrderbookSync::WaitingForSnapshot {
+            snapshot_rx: Some(snapshot_rx),
+            buffered_diffs: vec![],
+        };
+
+        (book, snapshot_tx)
+    }
+
+    fn bids(book: &BitstampBook) -> Vec<String> {
+        book.orderbook
+            .best_bids(10)
+            .iter()
+            .map(|(price, amount)| format!("{} {}", price, amount))
+            .collect()
+    }
+
+    /// Number of buffered diffs and whether snapshot is requested, or `None` if the book is synced
+    fn waiting_state(book: &BitstampBook) -> Option<(usize, bool)> {
+        match &book.sync {
+            BitstampOrderbookSync::WaitingForSnapshot {
+                snapshot_rx,
+                buffered_diffs,
+            } => Some((buffered_diffs.len(), snapshot_rx.is_some())),
+            BitstampOrderbookSync::Synced { .. } => None,
+        }
+    }
+
+    #[tokio::test]
+    async fn requests_snapshot_after_first_diff() {
+        let mut book = BitstampBook::new(&Pair::parse("ethbtc").unwrap(), "ethbtc");
+        book.snapshot_url = Url::parse("http://127.0.0.1:1/order_book/ethbtc/").unwrap();
+        assert_eq!(waiting_state(&book), Some((0, false)));
+
+        assert_eq!(book.handle_diff(diff(1000, &[("1", "1")])).unwrap(), None);
+        assert_eq!(waiting_state(&book), Some((1, true)));
+    }
+
+    #[test]
+    fn applies_buffered_diffs_newer_than_snapshot() {
+        let (mut book, snapshot_tx) = new_book();
+
+        assert_eq!(book.handle_diff(diff(1000, &[("1", "1")])).unwrap(), None);
+        assert_eq!(book.handle_diff(diff(2000, &[("2", "1")])).unwrap(), None);
+
+        // Snapshot already contains the first diff
+        snapshot_tx.send(diff(1500, &[("1", "5")])).unwrap();
+        assert_eq!(
+            book.handle_diff(diff(3000, &[("3", "1")])).unwrap(),
+            Some(3000)
+        );
+
+        assert_eq!(bids(&book), ["3 1", "2 1", "1 5"]);
+    }
+
+    #[test]
+    fn skips_diffs_older_than_orderbook() {
+        let (mut book, snapshot_tx) = new_book();
+        snapshot_tx.send(diff(2000, &[("1", "5")])).unwrap();
+        assert_eq!(book.handle_diff(diff(2000, &[])).unwrap(), Some(2000));
+
+        assert_eq!(
+            book.handle_diff(diff(1500, &[("1", "1")])).unwrap(),
+            Some(2000)
+        );
+        assert_eq!(
+            book.handle_diff(diff(2500, &[("1", "0")])).unwrap(),
+            Some(2500)
+        );
+        assert!(bids(&book).is_empty());
+    }
+
+    #[tokio::test]
+    async fn requests_new_snapshot_if_it_is_older_than_first_diff() {
+        let (mut book, snapshot_tx) = new_book();
+        snapshot_tx.send(diff(900, &[("1", "5")])).unwrap();
+
+        // Changes between the snapshot and the diff are unknown
+        assert_eq!(book.handle_diff(diff(1000, &[("2", "1")])).unwrap(), None);
+
+        assert!(bids(&book).is_empty());
+        assert_eq!(waiting_state(&book), Some((1, true)));
+    }
+
+    #[tokio::test]
+    async fn resyncs_with_new_snapshot() {
+        let (mut book, snapshot_tx) = new_book();
+        snapshot_tx.send(diff(1000, &[("1", "5")])).unwrap();
+        assert_eq!(book.handle_diff(diff(1000, &[])).unwrap(), Some(1000));
+
+        // Like after reconnection, when diffs could be missed
+        book.resync();
+        assert!(bids(&book).is_empty());
+        assert_eq!(waiting_state(&book), Some((0, false)));
+
+        assert_eq!(book.handle_diff(diff(5000, &[("2", "1")])).unwrap(), None);
+        assert_eq!(waiting_state(&book), Some((1, true)));
+    }
+
+    #[tokio::test]
+    async fn resyncs_if_snapshot_is_not_received_in_time() {
+        let (mut book, _snapshot_tx) = new_book();
+
+        for microtimestamp in 0..=BITSTAMP_MAX_BUFFERED_DIFFS as u64 {
+            assert_eq!(book.handle_diff(diff(microtimestamp, &[])).unwrap(), None);
+        }
+
+        assert_eq!(waiting_state(&book), Some((0, false)));
+    }
 }
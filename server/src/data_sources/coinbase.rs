@@ -0,0 +1,358 @@
+use std::{collections::HashMap, error::Error};
+
+use serde::Deserialize;
+use url::Url;
+
+use super::adapter::{AdapterMessage, ExchangeAdapter};
+use super::local_orderbook::LocalOrderbook;
+use super::symbol::Pair;
+use crate::decimal::Decimal;
+use crate::helpers::{get_env_var_or_default, parse_utc_timestamp_ms};
+
+#[derive(Deserialize, Debug)]
+struct CoinbaseApiIncomingMessage {
+    /// `l2_data`, `subscriptions`, `heartbeats`. Not present in error messages.
+    channel: Option<String>,
+    /// Increments by 1 with every message of the connection
+    sequence_num: Option<u64>,
+    /// `error` for error messages
+    #[serde(rename = "type")]
+    message_type: Option<String>,
+    message: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CoinbaseApiLevel2Message {
+    /// Exchange time of the message, like `2023-02-09T20:32:50.714964855Z`
+    timestamp: String,
+    events: Vec<CoinbaseApiLevel2Event>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CoinbaseApiLevel2Event {
+    /// `snapshot` or `update`
+    #[serde(rename = "type")]
+    event_type: String,
+    /// Like `ETH-BTC`
+    product_id: String,
+    updates: Vec<CoinbaseApiLevel2Update>,
+}
+
+#[derive(Deserialize, Debug)]
+struct CoinbaseApiLevel2Update {
+    /// `bid` or `offer`
+    side: String,
+    price_level: String,
+    /// New amount of the level, zero means the level is removed
+    new_quantity: String,
+}
+
+pub const COINBASE_EXCHANGE_NAME: &str = "coinbase";
+const DEFAULT_COINBASE_API_URL: &str = "wss://advanced-trade-ws.coinbase.com";
+const COINBASE_LEVEL2_CHANNEL: &str = "level2";
+
+pub fn new_adapter(pairs: &[Pair], depth: u16) -> Box<dyn ExchangeAdapter> {
+    Box::new(CoinbaseAdapter::new(pairs, depth))
+}
+
+/// Local orderbook of a single pair
+struct CoinbaseBook {
+    pair: Pair,
+    orderbook: LocalOrderbook,
+    /// Whether snapshot is received after (re)subscription
+    is_synced: bool,
+}
+
+/// Level2 channel: snapshot followed by updates of the full orderbook.
+/// Exchange feed level2 messages have no sequence numbers, so the Advanced Trade feed is used,
+/// where every message has `sequence_num` and dropped messages can be detected.
+/// Docs: https://docs.cdp.coinbase.com/advanced-trade/docs/ws-channels#level2-channel
+pub struct CoinbaseAdapter {
+    url: Url,
+    depth: u16,
+    /// Orderbooks by Coinbase product ID, like `ETH-BTC`
+    books: HashMap<String, CoinbaseBook>,
+    /// Sequence number is common for all products of the connection
+    last_sequence_num: Option<u64>,
+}
+
+impl CoinbaseAdapter {
+    pub fn new(pairs: &[Pair], depth: u16) -> Self {
+        let url = get_env_var_or_default("COINBASE_API_URL", DEFAULT_COINBASE_API_URL.to_string());
+        let url = Url::parse(&url).expect("Failed to parse Coinbase API URL");
+
+        Self::with_url(url, pairs, depth)
+    }
+
+    fn with_url(url: Url, pairs: &[Pair], depth: u16) -> Self {
+        let books = pairs
+            .iter()
+            .map(|pair| {
+                let book = CoinbaseBook {
+                    pair: pair.clone(),
+                    orderbook: LocalOrderbook::default(),
+                    is_synced: false,
+                };
+                (pair.exchange_symbol(COINBASE_EXCHANGE_NAME), book)
+            })
+            .collect();
+
+        Self {
+            url,
+            depth,
+            books,
+            last_sequence_num: None,
+        }
+    }
+
+    fn request_message(&self, request_type: &str) -> String {
+        let product_ids: Vec<String> = self
+            .books
+            .keys()
+            .map(|product_id| format!(r#""{}""#, product_id))
+            .collect();
+
+        format!(
+            r#"{{"type": "{}", "product_ids": [{}], "channel": "{}"}}"#,
+            request_type,
+            product_ids.join(", "),
+            COINBASE_LEVEL2_CHANNEL
+        )
+    }
+
+    fn reset(&mut self) {
+        for book in self.books.values_mut() {
+            book.orderbook.clear();
+            book.is_synced = false;
+        }
+    }
+
+    /// Returns `false` if some messages were dropped
+    fn check_sequence_num(&mut self, sequence_num: u64) -> bool {
+        let previous_sequence_num = self.last_sequence_num.replace(sequence_num);
+
+        match previous_sequence_num {
+            Some(previous_sequence_num) if sequence_num != previous_sequence_num + 1 => {
+                println!(
+                    "Coinbase messages are dropped: expected sequence number {}, got {}",
+                    previous_sequence_num + 1,
+                    sequence_num
+                );
+                false
+            }
+            _ => true,
+        }
+    }
+
+    fn handle_level2_message(
+        &mut self,
+        message: CoinbaseApiLevel2Message,
+    ) -> Result<AdapterMessage, Box<dyn Error + Send + Sync>> {
+        let timestamp = parse_utc_timestamp_ms(&message.timestamp)?;
+        let mut updated_product_id = None;
+
+        for event in message.events {
+            let book = self
+                .books
+                .get_mut(&event.product_id)
+                .ok_or_else(|| format!("Unexpected Coinbase product: {}", event.product_id))?;
+
+            if event.event_type == "snapshot" {
+                book.orderbook.clear();
+                book.is_synced = true;
+            } else if !book.is_synced {
+                // Updates which are received before the snapshot
+                continue;
+            }
+
+            for update in event.updates {
+                let price: Decimal = update.price_level.parse()?;
+                let amount: Decimal = update.new_quantity.parse()?;
+
+                match update.side.as_str() {
+                    "bid" => book.orderbook.update_bid(price, amount),
+                    "offer" => book.orderbook.update_ask(price, amount),
+                    side => return Err(format!("Unknown Coinbase orderbook side: {}", side).into()),
+                }
+            }
+
+            updated_product_id = Some(event.product_id);
+        }
+
+        Ok(
+            match updated_product_id.and_then(|product_id| self.books.get(&product_id)) {
+                Some(book) => AdapterMessage::Orderbook(
+                    book.pair.clone(),
+                    book.orderbook.to_exchange_orderbook_data(
+                        COINBASE_EXCHANGE_NAME,
+                        self.depth,
+                        Some(timestamp),
+                    ),
+                ),
+                None => AdapterMessage::Ignore,
+            },
+        )
+    }
+}
+
+impl ExchangeAdapter for CoinbaseAdapter {
+    fn name(&self) -> &'static str {
+        COINBASE_EXCHANGE_NAME
+    }
+
+    fn url(&self) -> Url {
+        self.url.clone()
+    }
+
+    fn on_connected(&mut self) {
+        // Sequence numbers start from 0 for every connection
+        self.reset();
+        self.last_sequence_num = None;
+    }
+
+    fn subscribe_messages(&self) -> Vec<String> {
+        vec![self.request_message("subscribe")]
+    }
+
+    fn unsubscribe_messages(&self) -> Vec<String> {
+        vec![self.request_message("unsubscribe")]
+    }
+
+    fn depth_limit(&self) -> Option<u16> {
+        None
+    }
+
+    fn parse_message(
+        &mut self,
+        data: &[u8],
+    ) -> Result<AdapterMessage, Box<dyn Error + Send + Sync>> {
+        let message: CoinbaseApiIncomingMessage = serde_json::from_slice(data)?;
+
+        if message.message_type.as_deref() == Some("error") {
+            println!(
+                "Error from Coinbase API: {}",
+                message.message.unwrap_or_default()
+            );
+            return Ok(AdapterMessage::Ignore);
+        }
+
+        if let Some(sequence_num) = message.sequence_num {
+            if !self.check_sequence_num(sequence_num) {
+                // Dropped messages may belong to any product
+                self.reset();
+                return Ok(AdapterMessage::Resubscribe);
+            }
+        }
+
+        if message.channel.as_deref() == Some("l2_data") {
+            let level2_message: CoinbaseApiLevel2Message = serde_json::from_slice(data)?;
+            return self.handle_level2_message(level2_message);
+        }
+
+        Ok(AdapterMessage::Ignore)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use futures_util::{SinkExt, StreamExt};
+    use tokio::net::TcpListener;
+    use tokio_tungstenite::{accept_async, connect_async, tungstenite::Message};
+
+    use super::*;
+
+    fn level2_message(
+        sequence_num: u64,
+        event_type: &str,
+        updates: &[(&str, &str, &str)],
+    ) -> String {
+        let updates: Vec<String> = updates
+            .iter()
+            .map(|(side, price, quantity)| {
+                format!(
+                    r#"{{"side": "{}", "event_time": "2023-02-09T20:32:50.714964855Z", "price_level": "{}", "new_quantity": "{}"}}"#,
+                    side, price, quantity
+                )
+            })
+            .collect();
+
+        format!(
+            r#"{{"channel": "l2_data", "client_id": "", "timestamp": "2023-02-09T20:32:50.714964855Z", "sequence_num": {}, "events": [{{"type": "{}", "product_id": "ETH-BTC", "updates": [{}]}}]}}"#,
+            sequence_num,
+            event_type,
+            updates.join(", ")
+        )
+    }
+
+    #[tokio::test]
+    async fn resubscribes_on_sequence_gap_from_mock_server() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+
+        let messages = vec![
+            level2_message(
+                0,
+                "snapshot",
+                &[("bid", "0.049", "2"), ("offer", "0.0505", "1")],
+            ),
+            level2_message(1, "update", &[("bid", "0.049", "0"), ("bid", "0.048", "3")]),
+            // Message 2 is dropped
+            level2_message(3, "update", &[("offer", "0.051", "4")]),
+        ];
+
+        let server = tokio::spawn(async move {
+            let (stream, _) = listener.accept().await.unwrap();
+            let mut socket = accept_async(stream).await.unwrap();
+
+            let subscribe_message = socket.next().await.unwrap().unwrap();
+            assert!(subscribe_message.to_text().unwrap().contains("ETH-BTC"));
+
+            for message in messages {
+                socket.send(Message::Text(message)).await.unwrap();
+            }
+        });
+
+        let url = Url::parse(&format!("ws://{}", address)).unwrap();
+        let pair = Pair::parse("ethbtc").unwrap();
+        let mut adapter = CoinbaseAdapter::with_url(url, std::slice::from_ref(&pair), 10);
+
+        let (mut socket, _) = connect_async(adapter.url()).await.unwrap();
+        adapter.on_connected();
+        for subscribe_message in adapter.subscribe_messages() {
+            socket.send(Message::Text(subscribe_message)).await.unwrap();
+        }
+
+        let mut messages = Vec::new();
+        for _ in 0..3 {
+            let data = socket.next().await.unwrap().unwrap().into_data();
+            messages.push(adapter.parse_message(&data).unwrap());
+        }
+        server.await.unwrap();
+
+        match &messages[0] {
+            AdapterMessage::Orderbook(orderbook_pair, data) => {
+                assert_eq!(*orderbook_pair, pair);
+                assert_eq!(data.timestamp, 1675974770714);
+                assert_eq!(
+                    data.bids,
+                    vec![("0.049".parse().unwrap(), "2".parse().unwrap())]
+                );
+                assert_eq!(
+                    data.asks,
+                    vec![("0.0505".parse().unwrap(), "1".parse().unwrap())]
+                );
+            }
+            _ => panic!("Expected orderbook after snapshot"),
+        }
+        match &messages[1] {
+            AdapterMessage::Orderbook(_, data) => {
+                assert_eq!(
+                    data.bids,
+                    vec![("0.048".parse().unwrap(), "3".parse().unwrap())]
+                );
+            }
+            _ => panic!("Expected orderbook after update"),
+        }
+        assert!(matches!(messages[2], AdapterMessage::Resubscribe));
+    }
+}
@@ -0,0 +1,267 @@
+use std::{collections::HashMap, error::Error, time::Duration};
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::time::{timeout, timeout_at, Instant};
+use tokio_tungstenite::{connect_async, tungstenite::Message};
+
+use super::adapter::{AdapterMessage, ExchangeAdapter};
+use super::output_data_format::ExchangeOrderbookData;
+use super::reconnect::ReconnectPolicy;
+use super::status::{update_exchange_status, ConnectionState, ExchangeStatuses};
+use super::symbol::Pair;
+
+// Reconnect a minute before the exchange closes the connection by itself
+const RECONNECTION_MARGIN_SECONDS: u64 = 60;
+
+/// Orderbook data channels of every pair served by a connection
+pub type PairSenders = HashMap<Pair, flume::Sender<ExchangeOrderbookData>>;
+
+/// Why a healthy connection was finished
+enum ConnectionEnd {
+    /// Connection is too old, silent even after ping, or exchange asked to reconnect.
+    /// We can reconnect immediately, it's not a failed attempt.
+    Reconnect,
+    /// Nobody listens to the data anymore
+    ChannelClosed,
+}
+
+type ConnectionResult = Result<ConnectionEnd, Box<dyn Error + Send + Sync>>;
+
+/// Settings shared by all exchange connections
+#[derive(Debug, Clone, Copy)]
+pub struct ConnectionSettings {
+    pub depth: u16,
+    pub reconnect_policy: ReconnectPolicy,
+    /// If there are no orderbook updates for this long, the exchange is pinged.
+    /// If nothing at all is received after one more interval, the connection is restarted.
+    pub watchdog_timeout: Duration,
+}
+
+/// Shared connection loop for all exchanges: connects, subscribes, answers pings,
+/// reconnects when connection is closed, too old or silent, and sends parsed data to `txs` of its pair.
+/// Connection errors are retried according to `settings.reconnect_policy`.
+pub fn spawn_thread(
+    mut adapter: Box<dyn ExchangeAdapter>,
+    settings: ConnectionSettings,
+    txs: PairSenders,
+    statuses: ExchangeStatuses,
+) -> tokio::task::JoinHandle<()> {
+    let name = adapter.name();
+    let depth = settings.depth;
+
+    if let Some(depth_limit) = adapter.depth_limit() {
+        if depth > depth_limit {
+            println!(
+                "[WARNING] {} API supports only depth <= {}. Only first {} levels will be used.",
+                name, depth_limit, depth_limit
+            );
+        }
+    }
+
+    update_exchange_status(&statuses, name, |status| {
+        status.state = ConnectionState::Connecting
+    });
+
+    tokio::spawn(async move {
+        loop {
+            let result = run_connection(adapter.as_mut(), settings, &txs, &statuses).await;
+
+            match result {
+                Ok(ConnectionEnd::ChannelClosed) => {
+                    println!("Channel is closed. Unsubscribing from {} API...", name);
+                    break;
+                }
+                Ok(ConnectionEnd::Reconnect) => {
+                    update_exchange_status(&statuses, name, |status| {
+                        status.state = ConnectionState::Connecting
+                    });
+                }
+                Err(error) => {
+                    println!("{} API connection error: {}", name, error);
+
+                    let attempt = statuses
+                        .read()
+                        .expect("Failed to lock exchange statuses for reading")
+                        .get(name)
+                        .map(|status| status.reconnect_attempts)
+                        .unwrap_or_default();
+
+                    let delay = match settings.reconnect_policy.delay(attempt) {
+                        Some(delay) => delay,
+                        None => {
+                            println!(
+                                "{} API reconnection attempts limit is reached. Giving up.",
+                                name
+                            );
+                            update_exchange_status(&statuses, name, |status| {
+                                status.state = ConnectionState::Failed
+                            });
+                            break;
+                        }
+                    };
+
+                    println!(
+                        "Reconnecting to {} API in {} ms (attempt {})...",
+                        name,
+                        delay.as_millis(),
+                        attempt + 1
+                    );
+                    update_exchange_status(&statuses, name, |status| {
+                        status.state = ConnectionState::BackingOff;
+                        status.reconnect_attempts += 1;
+                    });
+
+                    tokio::time::sleep(delay).await;
+
+                    update_exchange_status(&statuses, name, |status| {
+                        status.state = ConnectionState::Connecting
+                    });
+                }
+            }
+        }
+    })
+}
+
+async fn run_connection(
+    adapter: &mut dyn ExchangeAdapter,
+    settings: ConnectionSettings,
+    txs: &PairSenders,
+    statuses: &ExchangeStatuses,
+) -> ConnectionResult {
+    let name = adapter.name();
+    // Exchanges with a depth limit send fewer levels, so their data is cut at the limit
+    let depth = adapter.depth_limit().map_or(settings.depth, |depth_limit| {
+        settings.depth.min(depth_limit)
+    });
+    let reconnection_frequency_seconds = adapter
+        .connection_age_limit_seconds()
+        .saturating_sub(RECONNECTION_MARGIN_SECONDS);
+
+    let (mut socket, _) = timeout(settings.watchdog_timeout, connect_async(adapter.url()))
+        .await
+        .map_err(|_| "connection timeout")??;
+
+    adapter.on_connected();
+
+    for subscribe_message in adapter.subscribe_messages() {
+        socket.send(Message::Text(subscribe_message)).await?;
+    }
+
+    let connection_time = std::time::Instant::now();
+
+    // Watchdog: time of the last orderbook update, and whether the exchange was already pinged since then
+    let mut last_update_time = Instant::now();
+    let mut is_pinged = false;
+
+    let connection_end = loop {
+        if connection_time.elapsed().as_secs() > reconnection_frequency_seconds {
+            println!("{} API connection is too old. Reconnecting...", name);
+            break ConnectionEnd::Reconnect;
+        }
+
+        let deadline = if is_pinged {
+            last_update_time + settings.watchdog_timeout * 2
+        } else {
+            last_update_time + settings.watchdog_timeout
+        };
+
+        let message = match timeout_at(deadline, socket.next()).await {
+            Ok(Some(message)) => message?,
+            Ok(None) => return Err("connection is closed by server".into()),
+            Err(_) if is_pinged => {
+                println!(
+                    "{} API sent nothing for {} ms after ping. Reconnecting...",
+                    name,
+                    settings.watchdog_timeout.as_millis()
+                );
+                break ConnectionEnd::Reconnect;
+            }
+            Err(_) => {
+                println!(
+                    "{} API sent no orderbook updates for {} ms. Pinging...",
+                    name,
+                    settings.watchdog_timeout.as_millis()
+                );
+                update_exchange_status(statuses, name, |status| status.stalls += 1);
+
+                let ping_message = match adapter.ping_message() {
+                    Some(ping_message) => Message::Text(ping_message),
+                    None => Message::Ping(vec![]),
+                };
+                socket.send(ping_message).await?;
+                is_pinged = true;
+                continue;
+            }
+        };
+
+        if is_pinged {
+            // Any frame after the ping, like pong or heartbeat, means that the connection is alive
+            // and the feed is just quiet. Exchange is live only when it sends orderbooks.
+            last_update_time = Instant::now();
+            is_pinged = false;
+        }
+
+        if message.is_ping() {
+            // if we don't send PONG, the connection will be closed by server
+            socket.send(Message::Pong(message.into_data())).await?;
+            continue;
+        }
+
+        if message.is_pong() {
+            continue;
+        }
+
+        let data = message.into_data();
+
+        match adapter.parse_message(&data) {
+            Ok(AdapterMessage::Orderbook(pair, mut orderbook_data)) => {
+                last_update_time = Instant::now();
+                is_pinged = false;
+
+                orderbook_data.trim(depth);
+
+                let tx = match txs.get(&pair) {
+                    Some(tx) => tx,
+                    None => {
+                        println!("{} API sent orderbook of unexpected pair {}", name, pair);
+                        continue;
+                    }
+                };
+
+                if tx.send_async(orderbook_data).await.is_err() {
+                    break ConnectionEnd::ChannelClosed;
+                }
+
+                update_exchange_status(statuses, name, |status| {
+                    status.state = ConnectionState::Live;
+                    status.reconnect_attempts = 0;
+                });
+            }
+            Ok(AdapterMessage::Reconnect) => {
+                println!("{} API requested reconnect. Reconnecting...", name);
+                break ConnectionEnd::Reconnect;
+            }
+            Ok(AdapterMessage::Resubscribe) => {
+                println!("Resubscribing to {} API...", name);
+
+                for unsubscribe_message in adapter.unsubscribe_messages() {
+                    socket.send(Message::Text(unsubscribe_message)).await?;
+                }
+                for subscribe_message in adapter.subscribe_messages() {
+                    socket.send(Message::Text(subscribe_message)).await?;
+                }
+            }
+            Ok(AdapterMessage::Ignore) => {}
+            Err(error) => {
+                println!("Error parsing {} API message: {}", name, error);
+            }
+        }
+    };
+
+    // Connection may be already closed by server, so error here is not critical
+    if let Err(error) = socket.close(None).await {
+        println!("Failed to close {} API connection: {}", name, error);
+    }
+
+    Ok(connection_end)
+}
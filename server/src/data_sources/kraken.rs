@@ -0,0 +1,435 @@
+use std::{collections::HashMap, error::Error};
+
+use serde::Deserialize;
+use url::Url;
+
+use super::adapter::{AdapterMessage, ExchangeAdapter};
+use super::local_orderbook::LocalOrderbook;
+use super::snapshot::request_snapshot;
+use super::symbol::{Pair, KRAKEN_REST_API};
+use crate::decimal::Decimal;
+use crate::helpers::{get_env_var_or_default, parse_utc_timestamp_ms};
+
+#[derive(Deserialize, Debug)]
+struct KrakenApiIncomingMessage {
+    /// `book`, `heartbeat`, `status`, etc. Not present in responses to requests.
+    channel: Option<String>,
+    /// `subscribe`, `unsubscribe`, `pong`. Present only in responses to requests.
+    method: Option<String>,
+    success: Option<bool>,
+    error: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct KrakenApiBookMessage {
+    /// `snapshot` or `update`
+    #[serde(rename = "type")]
+    message_type: String,
+    data: Vec<KrakenApiBookData>,
+}
+
+#[derive(Deserialize, Debug)]
+struct KrakenApiBookData {
+    /// Like `ETH/BTC`
+    symbol: String,
+    bids: Vec<KrakenApiBookLevel>,
+    asks: Vec<KrakenApiBookLevel>,
+    /// CRC32 of top 10 levels of the book after this message is applied
+    checksum: u32,
+    /// Exchange time of the update, like `2023-10-06T17:35:55.440295Z`
+    timestamp: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct KrakenApiBookLevel {
+    price: Decimal,
+    qty: Decimal,
+}
+
+/// REST API `/AssetPairs` response. Precision is needed to calculate the book checksum.
+#[derive(Deserialize, Debug)]
+struct KrakenApiAssetPairsResponse {
+    result: HashMap<String, KrakenApiAssetPair>,
+}
+
+#[derive(Deserialize, Debug, Clone, Copy)]
+struct KrakenApiAssetPair {
+    /// Price precision
+    pair_decimals: usize,
+    /// Quantity precision
+    lot_decimals: usize,
+}
+
+pub const KRAKEN_EXCHANGE_NAME: &str = "kraken";
+const DEFAULT_KRAKEN_API_URL: &str = "wss://ws.kraken.com/v2";
+const DEFAULT_KRAKEN_REST_API_URL: &str = "https://api.kraken.com/0/public";
+const KRAKEN_SUPPORTED_DEPTH_LIMITS: [u16; 5] = [10, 25, 100, 500, 1000];
+// Checksum is calculated from top 10 levels of each side
+const KRAKEN_CHECKSUM_DEPTH: u16 = 10;
+
+pub fn new_adapter(pairs: &[Pair], depth: u16) -> Box<dyn ExchangeAdapter> {
+    Box::new(KrakenAdapter::new(pairs, depth))
+}
+
+/// Local orderbook of a single pair
+struct KrakenBook {
+    pair: Pair,
+    orderbook: LocalOrderbook,
+    /// Whether snapshot is received after (re)subscription
+    is_synced: bool,
+    asset_pair_rx: flume::Receiver<KrakenApiAssetPairsResponse>,
+    asset_pair: Option<KrakenApiAssetPair>,
+}
+
+impl KrakenBook {
+    fn new(pair: &Pair) -> Self {
+        let rest_url = get_env_var_or_default(
+            "KRAKEN_REST_API_URL",
+            DEFAULT_KRAKEN_REST_API_URL.to_string(),
+        );
+        let asset_pair_url = format!(
+            "{}/AssetPairs?pair={}",
+            rest_url,
+            pair.exchange_symbol(KRAKEN_REST_API)
+        );
+        let asset_pair_url =
+            Url::parse(&asset_pair_url).expect("Failed to parse Kraken REST API URL");
+
+        Self {
+            pair: pair.clone(),
+            orderbook: LocalOrderbook::default(),
+            is_synced: false,
+            asset_pair_rx: request_snapshot(KRAKEN_EXCHANGE_NAME, asset_pair_url),
+            asset_pair: None,
+        }
+    }
+
+    /// CRC32 of top 10 asks and bids, formatted as described in
+    /// https://docs.kraken.com/api/docs/guides/spot-ws-book-v2
+    fn checksum(&self, asset_pair: KrakenApiAssetPair) -> u32 {
+        let mut checksum_input = String::new();
+
+        let asks = self.orderbook.best_asks(KRAKEN_CHECKSUM_DEPTH);
+        let bids = self.orderbook.best_bids(KRAKEN_CHECKSUM_DEPTH);
+
+        for (price, amount) in asks.iter().chain(bids.iter()) {
+            checksum_input.push_str(&format_checksum_number(*price, asset_pair.pair_decimals));
+            checksum_input.push_str(&format_checksum_number(*amount, asset_pair.lot_decimals));
+        }
+
+        crc32fast::hash(checksum_input.as_bytes())
+    }
+
+    /// Returns `false` if checksum doesn't match and the book has to be resubscribed
+    fn apply_data(&mut self, data: KrakenApiBookData, subscription_depth: u16) -> bool {
+        if self.asset_pair.is_none() {
+            self.asset_pair = self
+                .asset_pair_rx
+                .try_recv()
+                .ok()
+                .and_then(|response| response.result.into_values().next());
+        }
+
+        for level in data.bids {
+            self.orderbook.update_bid(level.price, level.qty);
+        }
+        for level in data.asks {
+            self.orderbook.update_ask(level.price, level.qty);
+        }
+
+        // Levels which are out of subscription depth are not updated anymore
+        self.orderbook.truncate(subscription_depth);
+
+        // Checksum can't be validated until precision is known
+        if let Some(asset_pair) = self.asset_pair {
+            let checksum = self.checksum(asset_pair);
+            if checksum != data.checksum {
+                println!(
+                    "Kraken {} orderbook checksum mismatch: expected {}, got {}",
+                    self.pair, data.checksum, checksum
+                );
+                self.orderbook.clear();
+                self.is_synced = false;
+                return false;
+            }
+        }
+
+        true
+    }
+}
+
+/// Book channel of WebSocket API v2, with checksum validation
+/// Docs: https://docs.kraken.com/api/docs/websocket-v2/book
+pub struct KrakenAdapter {
+    url: Url,
+    /// Depth of subscription, one of `KRAKEN_SUPPORTED_DEPTH_LIMITS`
+    subscription_depth: u16,
+    depth: u16,
+    /// Orderbooks by Kraken WebSocket API symbol, like `ETH/BTC`
+    books: HashMap<String, KrakenBook>,
+}
+
+impl KrakenAdapter {
+    pub fn new(pairs: &[Pair], depth: u16) -> Self {
+        let url = get_env_var_or_default("KRAKEN_API_URL", DEFAULT_KRAKEN_API_URL.to_string());
+        let url = Url::parse(&url).expect("Failed to parse Kraken API URL");
+
+        let subscription_depth = KRAKEN_SUPPORTED_DEPTH_LIMITS
+            .iter()
+            .find(|&&limit| limit >= depth)
+            .unwrap_or(&KRAKEN_SUPPORTED_DEPTH_LIMITS[4])
+            .to_owned();
+
+        let books = pairs
+            .iter()
+            .map(|pair| {
+                (
+                    pair.exchange_symbol(KRAKEN_EXCHANGE_NAME),
+                    KrakenBook::new(pair),
+                )
+            })
+            .collect();
+
+        Self {
+            url,
+            subscription_depth,
+            depth,
+            books,
+        }
+    }
+
+    fn request_message(&self, method: &str) -> String {
+        let symbols: Vec<String> = self
+            .books
+            .keys()
+            .map(|symbol| format!(r#""{}""#, symbol))
+            .collect();
+
+        format!(
+            r#"{{"method": "{}", "params": {{"channel": "book", "symbol": [{}], "depth": {}}}}}"#,
+            method,
+            symbols.join(", "),
+            self.subscription_depth
+        )
+    }
+
+    fn handle_book_message(
+        &mut self,
+        message: KrakenApiBookMessage,
+    ) -> Result<AdapterMessage, Box<dyn Error + Send + Sync>> {
+        let mut updated_book = None;
+
+        for data in message.data {
+            let book = self
+                .books
+                .get_mut(&data.symbol)
+                .ok_or_else(|| format!("Unexpected Kraken symbol: {}", data.symbol))?;
+
+            if message.message_type == "snapshot" {
+                book.orderbook.clear();
+                book.is_synced = true;
+            } else if !book.is_synced {
+                continue;
+            }
+
+            let symbol = data.symbol.clone();
+            let timestamp = data
+                .timestamp
+                .as_deref()
+                .map(parse_utc_timestamp_ms)
+                .transpose()?;
+
+            if !book.apply_data(data, self.subscription_depth) {
+                return Ok(AdapterMessage::Resubscribe);
+            }
+
+            updated_book = Some((symbol, timestamp));
+        }
+
+        let updated_book = updated_book
+            .and_then(|(symbol, timestamp)| Some((self.books.get(&symbol)?, timestamp)));
+
+        Ok(match updated_book {
+            Some((book, timestamp)) => AdapterMessage::Orderbook(
+                book.pair.clone(),
+                book.orderbook.to_exchange_orderbook_data(
+                    KRAKEN_EXCHANGE_NAME,
+                    self.depth,
+                    timestamp,
+                ),
+            ),
+            None => AdapterMessage::Ignore,
+        })
+    }
+}
+
+impl ExchangeAdapter for KrakenAdapter {
+    fn name(&self) -> &'static str {
+        KRAKEN_EXCHANGE_NAME
+    }
+
+    fn url(&self) -> Url {
+        self.url.clone()
+    }
+
+    fn on_connected(&mut self) {
+        for book in self.books.values_mut() {
+            book.orderbook.clear();
+            book.is_synced = false;
+        }
+    }
+
+    fn subscribe_messages(&self) -> Vec<String> {
+        vec![self.request_message("subscribe")]
+    }
+
+    fn unsubscribe_messages(&self) -> Vec<String> {
+        vec![self.request_message("unsubscribe")]
+    }
+
+    fn ping_message(&self) -> Option<String> {
+        Some(r#"{"method": "ping"}"#.to_string())
+    }
+
+    fn depth_limit(&self) -> Option<u16> {
+        Some(KRAKEN_SUPPORTED_DEPTH_LIMITS[4])
+    }
+
+    fn parse_message(
+        &mut self,
+        data: &[u8],
+    ) -> Result<AdapterMessage, Box<dyn Error + Send + Sync>> {
+        let message: KrakenApiIncomingMessage = serde_json::from_slice(data)?;
+
+        if let Some(method) = message.method {
+            if message.success == Some(false) {
+                println!(
+                    "Error from Kraken API: {} ({})",
+                    message.error.unwrap_or_default(),
+                    method
+                );
+            }
+
+            return Ok(AdapterMessage::Ignore);
+        }
+
+        if message.channel.as_deref() == Some("book") {
+            let book_message: KrakenApiBookMessage = serde_json::from_slice(data)?;
+            return self.handle_book_message(book_message);
+        }
+
+        Ok(AdapterMessage::Ignore)
+    }
+}
+
+/// Number with fixed precision, without decimal point and leading zeros
+fn format_checksum_number(number: Decimal, decimals: usize) -> String {
+    let formatted = format!("{:.*}", decimals, number).replace('.', "");
+    let formatted = formatted.trim_start_matches('0');
+
+    if formatted.is_empty() {
+        "0".to_string()
+    } else {
+        formatted.to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Example book and checksum from Kraken's book checksum guide
+    const EXAMPLE_BOOK: &str = r#"{
+        "symbol": "ETH/BTC",
+        "asks": [
+            {"price": 0.05005, "qty": 0.00000500}, {"price": 0.05010, "qty": 0.00000500},
+            {"price": 0.05015, "qty": 0.00000500}, {"price": 0.05020, "qty": 0.00000500},
+            {"price": 0.05025, "qty": 0.00000500}, {"price": 0.05030, "qty": 0.00000500},
+            {"price": 0.05035, "qty": 0.00000500}, {"price": 0.05040, "qty": 0.00000500},
+            {"price": 0.05045, "qty": 0.00000500}, {"price": 0.05050, "qty": 0.00000500}
+        ],
+        "bids": [
+            {"price": 0.05000, "qty": 0.00000500}, {"price": 0.04995, "qty": 0.00000500},
+            {"price": 0.04990, "qty": 0.00000500}, {"price": 0.04980, "qty": 0.00000500},
+            {"price": 0.04975, "qty": 0.00000500}, {"price": 0.04970, "qty": 0.00000500},
+            {"price": 0.04965, "qty": 0.00000500}, {"price": 0.04960, "qty": 0.00000500},
+            {"price": 0.04955, "qty": 0.00000500}, {"price": 0.04950, "qty": 0.00000500}
+        ],
+        "checksum": 974947235
+    }"#;
+
+    fn example_book() -> KrakenBook {
+        KrakenBook {
+            pair: Pair::parse("ethbtc").unwrap(),
+            orderbook: LocalOrderbook::default(),
+            is_synced: true,
+            asset_pair_rx: flume::bounded(1).1,
+            asset_pair: Some(KrakenApiAssetPair {
+                pair_decimals: 5,
+                lot_decimals: 8,
+            }),
+        }
+    }
+
+    #[test]
+    fn formats_checksum_numbers() {
+        assert_eq!(
+            format_checksum_number("0.05005".parse().unwrap(), 5),
+            "5005"
+        );
+        assert_eq!(
+            format_checksum_number("0.000005".parse().unwrap(), 8),
+            "500"
+        );
+        assert_eq!(format_checksum_number("1.5".parse().unwrap(), 2), "150");
+        assert_eq!(format_checksum_number(Decimal::ZERO, 8), "0");
+    }
+
+    #[test]
+    fn validates_documented_checksum() {
+        let mut book = example_book();
+        let data: KrakenApiBookData = serde_json::from_str(EXAMPLE_BOOK).unwrap();
+
+        assert!(book.apply_data(data, 10));
+        assert_eq!(book.checksum(book.asset_pair.unwrap()), 974947235);
+    }
+
+    #[test]
+    fn detects_checksum_mismatch() {
+        let mut book = example_book();
+        let mut data: KrakenApiBookData = serde_json::from_str(EXAMPLE_BOOK).unwrap();
+        data.checksum += 1;
+
+        assert!(!book.apply_data(data, 10));
+        assert!(!book.is_synced);
+    }
+
+    #[test]
+    fn reports_exchange_time_of_updates() {
+        let mut adapter = KrakenAdapter::new(&[], 10);
+        let mut book = example_book();
+        book.is_synced = false;
+        adapter.books.insert("ETH/BTC".to_string(), book);
+
+        // Snapshot has no exchange time, so receive time is used
+        let snapshot = format!(
+            r#"{{"channel": "book", "type": "snapshot", "data": [{}]}}"#,
+            EXAMPLE_BOOK
+        );
+        match adapter.parse_message(snapshot.as_bytes()).unwrap() {
+            AdapterMessage::Orderbook(_, data) => {
+                assert_eq!(data.timestamp, data.received_timestamp)
+            }
+            _ => panic!("Expected orderbook after snapshot"),
+        }
+
+        let update = r#"{"channel": "book", "type": "update", "data": [{
+            "symbol": "ETH/BTC", "bids": [], "asks": [], "checksum": 974947235,
+            "timestamp": "2023-10-06T17:35:55.440295Z"
+        }]}"#;
+        match adapter.parse_message(update.as_bytes()).unwrap() {
+            AdapterMessage::Orderbook(_, data) => assert_eq!(data.timestamp, 1696613755440),
+            _ => panic!("Expected orderbook after update"),
+        }
+    }
+}
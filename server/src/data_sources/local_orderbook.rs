@@ -0,0 +1,145 @@
+use std::{collections::BTreeMap, error::Error};
+
+use crate::decimal::Decimal;
+
+use super::output_data_format::{parse_price_amount_tuples, ExchangeOrderbookData};
+
+#[derive(Debug)]
+struct Level {
+    amount: Decimal,
+    /// Price and amount exactly as exchange sent them. Some exchanges calculate checksums from them.
+    text: (String, String),
+}
+
+/// Full orderbook of a single exchange, maintained from snapshots and incremental updates
+#[derive(Debug, Default)]
+pub struct LocalOrderbook {
+    bids: BTreeMap<Decimal, Level>,
+    asks: BTreeMap<Decimal, Level>,
+}
+
+impl LocalOrderbook {
+    pub fn clear(&mut self) {
+        self.bids.clear();
+        self.asks.clear();
+    }
+
+    /// Sets amount of bid level. Zero amount removes the level.
+    pub fn update_bid(&mut self, price: Decimal, amount: Decimal) {
+        let text = (price.to_string(), amount.to_string());
+        update_level(&mut self.bids, price, amount, text);
+    }
+
+    /// Sets amount of ask level. Zero amount removes the level.
+    pub fn update_ask(&mut self, price: Decimal, amount: Decimal) {
+        let text = (price.to_string(), amount.to_string());
+        update_level(&mut self.asks, price, amount, text);
+    }
+
+    /// Removes levels beyond `depth` on each side
+    pub fn truncate(&mut self, depth: u16) {
+        while self.bids.len() > depth as usize {
+            self.bids.pop_first();
+        }
+        while self.asks.len() > depth as usize {
+            self.asks.pop_last();
+        }
+    }
+
+    /// Applies (price, amount) levels as exchanges send them, as strings
+    pub fn update_bids(
+        &mut self,
+        levels: &Vec<(String, String)>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let parsed_levels = parse_price_amount_tuples(levels)?;
+        for ((price, amount), text) in parsed_levels.into_iter().zip(levels.iter()) {
+            update_level(&mut self.bids, price, amount, text.clone());
+        }
+        Ok(())
+    }
+
+    /// Applies (price, amount) levels as exchanges send them, as strings
+    pub fn update_asks(
+        &mut self,
+        levels: &Vec<(String, String)>,
+    ) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let parsed_levels = parse_price_amount_tuples(levels)?;
+        for ((price, amount), text) in parsed_levels.into_iter().zip(levels.iter()) {
+            update_level(&mut self.asks, price, amount, text.clone());
+        }
+        Ok(())
+    }
+
+    /// Best `depth` bids, highest price first
+    pub fn best_bids(&self, depth: u16) -> Vec<(Decimal, Decimal)> {
+        self.bids
+            .iter()
+            .rev()
+            .take(depth as usize)
+            .map(|(price, level)| (*price, level.amount))
+            .collect()
+    }
+
+    /// Best `depth` bids as exchange sent them, highest price first
+    pub fn best_bids_text(&self, depth: u16) -> Vec<&(String, String)> {
+        self.bids
+            .values()
+            .rev()
+            .take(depth as usize)
+            .map(|level| &level.text)
+            .collect()
+    }
+
+    /// Best `depth` asks, lowest price first
+    pub fn best_asks(&self, depth: u16) -> Vec<(Decimal, Decimal)> {
+        self.asks
+            .iter()
+            .take(depth as usize)
+            .map(|(price, level)| (*price, level.amount))
+            .collect()
+    }
+
+    /// Best `depth` asks as exchange sent them, lowest price first
+    pub fn best_asks_text(&self, depth: u16) -> Vec<&(String, String)> {
+        self.asks
+            .values()
+            .take(depth as usize)
+            .map(|level| &level.text)
+            .collect()
+    }
+
+    /// Top `depth` levels in the unified format.
+    /// `timestamp` is exchange time in milliseconds, if exchange provides it.
+    pub fn to_exchange_orderbook_data(
+        &self,
+        exchange: &str,
+        depth: u16,
+        timestamp: Option<u64>,
+    ) -> ExchangeOrderbookData {
+        let asks = self.best_asks(depth);
+        let bids = self.best_bids(depth);
+
+        match timestamp {
+            Some(timestamp) => ExchangeOrderbookData::new_with_timestamp(
+                exchange.to_string(),
+                asks,
+                bids,
+                timestamp,
+            ),
+            None => ExchangeOrderbookData::new(exchange.to_string(), asks, bids),
+        }
+    }
+}
+
+fn update_level(
+    levels: &mut BTreeMap<Decimal, Level>,
+    price: Decimal,
+    amount: Decimal,
+    text: (String, String),
+) {
+    if amount.is_zero() {
+        levels.remove(&price);
+    } else {
+        levels.insert(price, Level { amount, text });
+    }
+}
@@ -1,17 +1,171 @@
+use std::{collections::HashMap, error::Error, time::Duration};
+
+use crate::helpers::get_env_var_or_default;
+
 // Unified output data format
 pub mod output_data_format;
 use output_data_format::ExchangeOrderbookData;
 
+// Common exchange interface and connection handling
+mod adapter;
+mod connection;
+mod local_orderbook;
+mod reconnect;
+mod snapshot;
+pub mod status;
+pub mod symbol;
+use adapter::ExchangeAdapter;
+use connection::{ConnectionSettings, PairSenders};
+use reconnect::ReconnectPolicy;
+use status::ExchangeStatuses;
+use symbol::{Pair, PairListings};
+
 // Exchanges
 mod binance;
+mod bitfinex;
 mod bitstamp;
+mod coinbase;
+mod kraken;
+mod okx;
+
+const DEFAULT_WATCHDOG_TIMEOUT_MS: u64 = 10 * 1000; // 10 seconds
+
+/// Orderbook data of a single pair, from all its exchanges
+pub struct PairDataReceiver {
+    pub pair: Pair,
+    /// Exchanges which are aggregated for the pair
+    pub exchanges: Vec<&'static str>,
+    pub data_rx: flume::Receiver<ExchangeOrderbookData>,
+}
+
+type AdapterConstructor = fn(&[Pair], u16) -> Box<dyn ExchangeAdapter>;
+
+/// All supported exchanges. To add a new one, implement `ExchangeAdapter`, register it here
+/// and add its symbol format to the symbol registry.
+const EXCHANGE_ADAPTERS: [(&str, AdapterConstructor); 6] = [
+    (binance::BINANCE_EXCHANGE_NAME, binance::new_adapter),
+    (bitstamp::BITSTAMP_EXCHANGE_NAME, bitstamp::new_adapter),
+    (kraken::KRAKEN_EXCHANGE_NAME, kraken::new_adapter),
+    (coinbase::COINBASE_EXCHANGE_NAME, coinbase::new_adapter),
+    (okx::OKX_EXCHANGE_NAME, okx::new_adapter),
+    (bitfinex::BITFINEX_EXCHANGE_NAME, bitfinex::new_adapter),
+];
+
+/// Orderbook data of every symbol, from all exchanges which list it.
+/// Each exchange uses a single connection for all symbols.
+pub fn get_data_rxs(
+    symbols: &[String],
+    depth: u16,
+    exchange_statuses: ExchangeStatuses,
+) -> Result<Vec<PairDataReceiver>, Box<dyn Error>> {
+    let requested_exchanges = get_env_var_or_default("EXCHANGES", String::new());
+
+    let mut pairs: Vec<Pair> = vec![];
+    for symbol in symbols {
+        let pair = Pair::parse(symbol)?;
+        if !pairs.contains(&pair) {
+            pairs.push(pair);
+        }
+    }
+
+    let exchanges: Vec<&str> = EXCHANGE_ADAPTERS.iter().map(|(name, _)| *name).collect();
+    let listings = PairListings::from_env(&exchanges)?;
+
+    let mut pair_exchanges = HashMap::new();
+    for pair in pairs.iter() {
+        let exchanges = select_exchanges(pair, &requested_exchanges, &listings)?;
+        pair_exchanges.insert(pair.clone(), exchanges);
+    }
+
+    let settings = ConnectionSettings {
+        depth,
+        reconnect_policy: ReconnectPolicy::from_env(),
+        watchdog_timeout: Duration::from_millis(get_env_var_or_default(
+            "WATCHDOG_TIMEOUT_MS",
+            DEFAULT_WATCHDOG_TIMEOUT_MS,
+        )),
+    };
+
+    let mut txs = HashMap::new();
+    let mut rxs = vec![];
+    for pair in pairs.iter() {
+        let (tx, rx) = flume::bounded::<ExchangeOrderbookData>(10);
+        txs.insert(pair.clone(), tx);
+        rxs.push(PairDataReceiver {
+            pair: pair.clone(),
+            exchanges: pair_exchanges[pair].clone(),
+            data_rx: rx,
+        });
+    }
+
+    for (exchange, adapter_constructor) in EXCHANGE_ADAPTERS {
+        let exchange_pairs: Vec<Pair> = pairs
+            .iter()
+            .filter(|pair| pair_exchanges[*pair].contains(&exchange))
+            .cloned()
+            .collect();
+
+        if exchange_pairs.is_empty() {
+            continue;
+        }
+
+        let exchange_txs: PairSenders = exchange_pairs
+            .iter()
+            .map(|pair| (pair.clone(), txs[pair].clone()))
+            .collect();
+
+        let adapter = adapter_constructor(&exchange_pairs, depth);
+        connection::spawn_thread(adapter, settings, exchange_txs, exchange_statuses.clone());
+    }
+
+    Ok(rxs)
+}
+
+pub fn is_supported_exchange(exchange: &str) -> bool {
+    EXCHANGE_ADAPTERS.iter().any(|(name, _)| *name == exchange)
+}
+
+/// Exchanges from `EXCHANGES` list, or all exchanges which list the pair if it's not set.
+/// Fails if some of requested exchanges don't list the pair.
+fn select_exchanges(
+    pair: &Pair,
+    requested_exchanges: &str,
+    listings: &PairListings,
+) -> Result<Vec<&'static str>, Box<dyn Error>> {
+    if requested_exchanges.trim().is_empty() {
+        let mut exchanges = vec![];
+
+        for (exchange, _) in EXCHANGE_ADAPTERS {
+            if listings.is_listed(pair, exchange) {
+                exchanges.push(exchange);
+            } else {
+                println!("{} is not listed on {}, skipping it", pair, exchange);
+            }
+        }
+
+        if exchanges.is_empty() {
+            return Err(format!("{} is not listed on any supported exchange", pair).into());
+        }
+
+        return Ok(exchanges);
+    }
 
-pub fn get_data_rx(symbol: String, depth: u16) -> flume::Receiver<ExchangeOrderbookData> {
-    let (tx, rx) = flume::bounded::<ExchangeOrderbookData>(10);
+    requested_exchanges
+        .split(',')
+        .map(|requested_exchange| {
+            let requested_exchange = requested_exchange.trim().to_lowercase();
 
-    binance::spawn_thread(symbol.clone(), depth, tx.clone());
+            let exchange = EXCHANGE_ADAPTERS
+                .iter()
+                .map(|(exchange, _)| *exchange)
+                .find(|exchange| *exchange == requested_exchange)
+                .ok_or_else(|| format!("Exchange `{}` is not supported", requested_exchange))?;
 
-    bitstamp::spawn_thread(symbol, depth, tx);
+            if !listings.is_listed(pair, exchange) {
+                return Err(format!("{} is not listed on {}", pair, exchange).into());
+            }
 
-    rx
+            Ok(exchange)
+        })
+        .collect()
 }
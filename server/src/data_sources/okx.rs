@@ -0,0 +1,332 @@
+use std::{collections::HashMap, error::Error};
+
+use serde::Deserialize;
+use url::Url;
+
+use super::adapter::{AdapterMessage, ExchangeAdapter};
+use super::local_orderbook::LocalOrderbook;
+use super::symbol::Pair;
+use crate::helpers::get_env_var_or_default;
+
+#[derive(Deserialize, Debug)]
+struct OkxApiIncomingMessage {
+    /// `subscribe`, `unsubscribe` or `error`. Not present in data messages.
+    event: Option<String>,
+    code: Option<String>,
+    msg: Option<String>,
+    /// `snapshot` or `update`. Present only in data messages.
+    action: Option<String>,
+}
+
+#[derive(Deserialize, Debug)]
+struct OkxApiBooksMessage {
+    arg: OkxApiSubscriptionArg,
+    action: String,
+    data: Vec<OkxApiBooksData>,
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct OkxApiSubscriptionArg {
+    /// Like `ETH-BTC`
+    inst_id: String,
+}
+
+/// Level is [price, amount, deprecated field, number of orders]
+type OkxApiBooksLevel = (String, String, String, String);
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct OkxApiBooksData {
+    asks: Vec<OkxApiBooksLevel>,
+    bids: Vec<OkxApiBooksLevel>,
+    /// Timestamp in milliseconds
+    ts: String,
+    /// Signed CRC32 of top 25 levels of the book after this message is applied
+    checksum: i32,
+    /// `seq_id` of the previous message, -1 for snapshots
+    prev_seq_id: i64,
+    seq_id: i64,
+}
+
+pub const OKX_EXCHANGE_NAME: &str = "okx";
+const DEFAULT_OKX_API_URL: &str = "wss://ws.okx.com:8443/ws/v5/public";
+const OKX_BOOKS_CHANNEL: &str = "books";
+const OKX_DEPTH_LIMIT: u16 = 400;
+// Checksum is calculated from top 25 levels of each side
+const OKX_CHECKSUM_DEPTH: u16 = 25;
+
+pub fn new_adapter(pairs: &[Pair], depth: u16) -> Box<dyn ExchangeAdapter> {
+    Box::new(OkxAdapter::new(pairs, depth))
+}
+
+/// Result of applying a message to the local orderbook
+enum OkxBookUpdate {
+    /// Orderbook is updated, with timestamp of the last applied data in milliseconds
+    Updated(u64),
+    /// Orderbook is not synced yet, the message is skipped
+    NotSynced,
+    /// Orderbook is broken and has to be rebuilt from a new snapshot
+    Broken(String),
+}
+
+/// Local orderbook of a single pair
+struct OkxBook {
+    pair: Pair,
+    orderbook: LocalOrderbook,
+    /// `seq_id` of the last applied message, `None` until snapshot is received
+    last_seq_id: Option<i64>,
+}
+
+impl OkxBook {
+    /// Signed CRC32 of top 25 bids and asks, interleaved, as described in
+    /// https://www.okx.com/docs-v5/en/#overview-websocket-checksum
+    fn checksum(&self) -> i32 {
+        let bids = self.orderbook.best_bids_text(OKX_CHECKSUM_DEPTH);
+        let asks = self.orderbook.best_asks_text(OKX_CHECKSUM_DEPTH);
+
+        let mut parts: Vec<&str> = Vec::new();
+        for i in 0..OKX_CHECKSUM_DEPTH as usize {
+            if let Some((price, amount)) = bids.get(i) {
+                parts.push(price);
+                parts.push(amount);
+            }
+            if let Some((price, amount)) = asks.get(i) {
+                parts.push(price);
+                parts.push(amount);
+            }
+        }
+
+        crc32fast::hash(parts.join(":").as_bytes()) as i32
+    }
+
+    /// Drops local orderbook, so it will be rebuilt from a new snapshot
+    fn reset(&mut self) {
+        self.orderbook.clear();
+        self.last_seq_id = None;
+    }
+
+    fn apply_message(
+        &mut self,
+        message: OkxApiBooksMessage,
+    ) -> Result<OkxBookUpdate, Box<dyn Error + Send + Sync>> {
+        let mut update = OkxBookUpdate::NotSynced;
+
+        for data in message.data {
+            if message.action == "snapshot" {
+                self.orderbook.clear();
+            } else {
+                match self.last_seq_id {
+                    None => return Ok(OkxBookUpdate::NotSynced),
+                    Some(last_seq_id) if data.prev_seq_id != last_seq_id => {
+                        return Ok(OkxBookUpdate::Broken(format!(
+                            "gap: expected previous sequence ID {}, got {}",
+                            last_seq_id, data.prev_seq_id
+                        )));
+                    }
+                    Some(_) => {}
+                }
+            }
+
+            let bids: Vec<(String, String)> = data
+                .bids
+                .into_iter()
+                .map(|(price, amount, _, _)| (price, amount))
+                .collect();
+            let asks: Vec<(String, String)> = data
+                .asks
+                .into_iter()
+                .map(|(price, amount, _, _)| (price, amount))
+                .collect();
+            self.orderbook.update_bids(&bids)?;
+            self.orderbook.update_asks(&asks)?;
+
+            let checksum = self.checksum();
+            if checksum != data.checksum {
+                return Ok(OkxBookUpdate::Broken(format!(
+                    "checksum mismatch: expected {}, got {}",
+                    data.checksum, checksum
+                )));
+            }
+
+            self.last_seq_id = Some(data.seq_id);
+            update = OkxBookUpdate::Updated(data.ts.parse()?);
+        }
+
+        Ok(update)
+    }
+}
+
+/// `books` channel (400 levels), with sequence and checksum validation
+/// Docs: https://www.okx.com/docs-v5/en/#order-book-trading-market-data-ws-order-book-channel
+pub struct OkxAdapter {
+    url: Url,
+    depth: u16,
+    /// Orderbooks by OKX instrument ID, like `ETH-BTC`
+    books: HashMap<String, OkxBook>,
+}
+
+impl OkxAdapter {
+    pub fn new(pairs: &[Pair], depth: u16) -> Self {
+        let url = get_env_var_or_default("OKX_API_URL", DEFAULT_OKX_API_URL.to_string());
+        let url = Url::parse(&url).expect("Failed to parse OKX API URL");
+
+        let books = pairs
+            .iter()
+            .map(|pair| {
+                let book = OkxBook {
+                    pair: pair.clone(),
+                    orderbook: LocalOrderbook::default(),
+                    last_seq_id: None,
+                };
+                (pair.exchange_symbol(OKX_EXCHANGE_NAME), book)
+            })
+            .collect();
+
+        Self { url, depth, books }
+    }
+
+    fn request_message(&self, op: &str) -> String {
+        let args: Vec<String> = self
+            .books
+            .keys()
+            .map(|inst_id| {
+                format!(
+                    r#"{{"channel": "{}", "instId": "{}"}}"#,
+                    OKX_BOOKS_CHANNEL, inst_id
+                )
+            })
+            .collect();
+
+        format!(r#"{{"op": "{}", "args": [{}]}}"#, op, args.join(", "))
+    }
+
+    fn handle_books_message(
+        &mut self,
+        message: OkxApiBooksMessage,
+    ) -> Result<AdapterMessage, Box<dyn Error + Send + Sync>> {
+        let inst_id = message.arg.inst_id.clone();
+        let book = self
+            .books
+            .get_mut(&inst_id)
+            .ok_or_else(|| format!("Unexpected OKX instrument: {}", inst_id))?;
+
+        match book.apply_message(message)? {
+            OkxBookUpdate::Updated(timestamp) => Ok(AdapterMessage::Orderbook(
+                book.pair.clone(),
+                book.orderbook.to_exchange_orderbook_data(
+                    OKX_EXCHANGE_NAME,
+                    self.depth,
+                    Some(timestamp),
+                ),
+            )),
+            OkxBookUpdate::NotSynced => Ok(AdapterMessage::Ignore),
+            OkxBookUpdate::Broken(error) => {
+                println!("OKX {} orderbook {}", book.pair, error);
+                book.reset();
+                Ok(AdapterMessage::Resubscribe)
+            }
+        }
+    }
+}
+
+impl ExchangeAdapter for OkxAdapter {
+    fn name(&self) -> &'static str {
+        OKX_EXCHANGE_NAME
+    }
+
+    fn url(&self) -> Url {
+        self.url.clone()
+    }
+
+    fn on_connected(&mut self) {
+        for book in self.books.values_mut() {
+            book.reset();
+        }
+    }
+
+    fn subscribe_messages(&self) -> Vec<String> {
+        vec![self.request_message("subscribe")]
+    }
+
+    fn unsubscribe_messages(&self) -> Vec<String> {
+        vec![self.request_message("unsubscribe")]
+    }
+
+    fn ping_message(&self) -> Option<String> {
+        Some("ping".to_string())
+    }
+
+    fn depth_limit(&self) -> Option<u16> {
+        Some(OKX_DEPTH_LIMIT)
+    }
+
+    fn parse_message(
+        &mut self,
+        data: &[u8],
+    ) -> Result<AdapterMessage, Box<dyn Error + Send + Sync>> {
+        // Response to `ping` is plain text
+        if data == b"pong" {
+            return Ok(AdapterMessage::Ignore);
+        }
+
+        let message: OkxApiIncomingMessage = serde_json::from_slice(data)?;
+
+        if message.event.as_deref() == Some("error") {
+            println!(
+                "Error from OKX API: {} (code: {})",
+                message.msg.unwrap_or_default(),
+                message.code.unwrap_or_default()
+            );
+            return Ok(AdapterMessage::Ignore);
+        }
+
+        if message.action.is_some() {
+            let books_message: OkxApiBooksMessage = serde_json::from_slice(data)?;
+            return self.handle_books_message(books_message);
+        }
+
+        Ok(AdapterMessage::Ignore)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn new_book(bids: &[(&str, &str)], asks: &[(&str, &str)]) -> OkxBook {
+        let to_levels = |levels: &[(&str, &str)]| -> Vec<(String, String)> {
+            levels
+                .iter()
+                .map(|(price, amount)| (price.to_string(), amount.to_string()))
+                .collect()
+        };
+
+        let mut book = OkxBook {
+            pair: Pair::parse("ethbtc").unwrap(),
+            orderbook: LocalOrderbook::default(),
+            last_seq_id: None,
+        };
+        book.orderbook.update_bids(&to_levels(bids)).unwrap();
+        book.orderbook.update_asks(&to_levels(asks)).unwrap();
+        book
+    }
+
+    /// Examples from https://www.okx.com/docs-v5/en/#overview-websocket-checksum
+    #[test]
+    fn calculates_documented_checksums() {
+        // `3366.1:7:3366.8:9:3366:6:3368:8`, the checksum is negative as signed integer
+        let book = new_book(
+            &[("3366.1", "7"), ("3366", "6")],
+            &[("3366.8", "9"), ("3368", "8")],
+        );
+        assert_eq!(book.checksum(), -1881014294);
+
+        // Sides of different length: `3366.1:7:3366.8:9:3368:8:3372:8`
+        let book = new_book(
+            &[("3366.1", "7")],
+            &[("3366.8", "9"), ("3368", "8"), ("3372", "8")],
+        );
+        assert_eq!(book.checksum(), 831078360);
+    }
+}
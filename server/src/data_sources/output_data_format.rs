@@ -1,31 +1,40 @@
-use std::time::{SystemTime, UNIX_EPOCH};
+use std::error::Error;
 
-use serde::Deserialize;
+use crate::decimal::Decimal;
+use crate::helpers::get_current_timestamp_ms;
 
 use super::binance::BinanceApiOrderBookMessage;
 use super::bitstamp::BitstampApiOrderBookData;
 
 /// Unified output data format
-#[derive(Deserialize, Debug, Clone)]
+#[derive(Debug, Clone)]
 pub struct ExchangeOrderbookData {
     pub exchange: String,
-    pub asks: Vec<(f64, f64)>, // price, amount
-    pub bids: Vec<(f64, f64)>, // price, amount
+    pub asks: Vec<(Decimal, Decimal)>, // price, amount
+    pub bids: Vec<(Decimal, Decimal)>, // price, amount
+    /// Exchange time if API provides it, otherwise receive time
     pub timestamp: u64,
+    /// Time when the data is received from exchange
+    pub received_timestamp: u64,
+    /// Depth to which sides are trimmed. A side with this many levels can continue on exchange.
+    pub trimmed_depth: Option<u16>,
 }
 
 impl ExchangeOrderbookData {
-    pub fn new(exchange: String, asks: Vec<(f64, f64)>, bids: Vec<(f64, f64)>) -> Self {
-        let timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Failed to get current time to create ExchangeOrderbookData")
-            .as_millis() as u64;
+    pub fn new(
+        exchange: String,
+        asks: Vec<(Decimal, Decimal)>,
+        bids: Vec<(Decimal, Decimal)>,
+    ) -> Self {
+        let timestamp = get_current_timestamp_ms();
 
         Self {
             exchange,
             asks,
             bids,
             timestamp,
+            received_timestamp: timestamp,
+            trimmed_depth: None,
         }
     }
 
@@ -33,8 +42,8 @@ impl ExchangeOrderbookData {
     /// Otherwise we use current time (see `new` method).
     pub fn new_with_timestamp(
         exchange: String,
-        asks: Vec<(f64, f64)>,
-        bids: Vec<(f64, f64)>,
+        asks: Vec<(Decimal, Decimal)>,
+        bids: Vec<(Decimal, Decimal)>,
         timestamp: u64,
     ) -> Self {
         Self {
@@ -42,52 +51,73 @@ impl ExchangeOrderbookData {
             asks,
             bids,
             timestamp,
+            received_timestamp: get_current_timestamp_ms(),
+            trimmed_depth: None,
         }
     }
+
+    /// Keep only first `depth` levels on each side
+    pub fn trim(&mut self, depth: u16) {
+        self.asks.truncate(depth as usize);
+        self.bids.truncate(depth as usize);
+        self.trimmed_depth = Some(depth);
+    }
+
+    /// Whether the exchange can have more asks than the data, see `trimmed_depth`
+    pub fn are_asks_trimmed(&self) -> bool {
+        self.is_side_trimmed(&self.asks)
+    }
+
+    /// Whether the exchange can have more bids than the data, see `trimmed_depth`
+    pub fn are_bids_trimmed(&self) -> bool {
+        self.is_side_trimmed(&self.bids)
+    }
+
+    fn is_side_trimmed(&self, levels: &[(Decimal, Decimal)]) -> bool {
+        self.trimmed_depth
+            .is_some_and(|depth| levels.len() >= depth as usize)
+    }
 }
 
-impl From<BinanceApiOrderBookMessage> for ExchangeOrderbookData {
-    fn from(binance_orderbook_message: BinanceApiOrderBookMessage) -> Self {
-        let exchange = "binance".to_string();
+impl TryFrom<BinanceApiOrderBookMessage> for ExchangeOrderbookData {
+    type Error = Box<dyn Error + Send + Sync>;
 
-        let asks = parse_price_amount_tuples(&binance_orderbook_message.asks)
-            .expect("Failed to parse Binance asks");
+    fn try_from(
+        binance_orderbook_message: BinanceApiOrderBookMessage,
+    ) -> Result<Self, Self::Error> {
+        let exchange = "binance".to_string();
 
-        let bids = parse_price_amount_tuples(&binance_orderbook_message.bids)
-            .expect("Failed to parse Binance bids");
+        let asks = parse_price_amount_tuples(&binance_orderbook_message.asks)?;
+        let bids = parse_price_amount_tuples(&binance_orderbook_message.bids)?;
 
-        Self::new(exchange, asks, bids)
+        Ok(Self::new(exchange, asks, bids))
     }
 }
 
-impl From<BitstampApiOrderBookData> for ExchangeOrderbookData {
-    fn from(bitstamp_orderbook_message: BitstampApiOrderBookData) -> Self {
-        let exchange = "bitstamp".to_string();
+impl TryFrom<BitstampApiOrderBookData> for ExchangeOrderbookData {
+    type Error = Box<dyn Error + Send + Sync>;
 
-        let asks = parse_price_amount_tuples(&bitstamp_orderbook_message.asks)
-            .expect("Failed to parse Bitstamp asks");
+    fn try_from(bitstamp_orderbook_message: BitstampApiOrderBookData) -> Result<Self, Self::Error> {
+        let exchange = "bitstamp".to_string();
 
-        let bids = parse_price_amount_tuples(&bitstamp_orderbook_message.bids)
-            .expect("Failed to parse Bitstamp bids");
+        let asks = parse_price_amount_tuples(&bitstamp_orderbook_message.asks)?;
+        let bids = parse_price_amount_tuples(&bitstamp_orderbook_message.bids)?;
 
-        let timestamp_in_seconds: u64 = bitstamp_orderbook_message
-            .timestamp
-            .parse()
-            .expect("Failed to parse Bitstamp timestamp");
-        let timestamp = timestamp_in_seconds * 1000;
+        let timestamp_in_microseconds: u64 = bitstamp_orderbook_message.microtimestamp.parse()?;
+        let timestamp = timestamp_in_microseconds / 1000;
 
-        Self::new_with_timestamp(exchange, asks, bids, timestamp)
+        Ok(Self::new_with_timestamp(exchange, asks, bids, timestamp))
     }
 }
 
-fn parse_price_amount_tuples(
+pub fn parse_price_amount_tuples(
     vec: &Vec<(String, String)>,
-) -> Result<Vec<(f64, f64)>, Box<dyn std::error::Error>> {
+) -> Result<Vec<(Decimal, Decimal)>, Box<dyn Error + Send + Sync>> {
     let mut result = Vec::new();
 
     for tuple in vec {
-        let price = tuple.0.parse::<f64>()?;
-        let amount = tuple.1.parse::<f64>()?;
+        let price = tuple.0.parse::<Decimal>()?;
+        let amount = tuple.1.parse::<Decimal>()?;
         result.push((price, amount));
     }
 
@@ -0,0 +1,54 @@
+use std::time::Duration;
+
+use rand::Rng;
+
+use crate::helpers::get_env_var_or_default;
+
+const DEFAULT_RECONNECT_INITIAL_DELAY_MS: u64 = 500;
+const DEFAULT_RECONNECT_MAX_DELAY_MS: u64 = 60 * 1000; // 1 minute
+const DEFAULT_RECONNECT_MAX_ATTEMPTS: u32 = 0; // 0 means unlimited
+
+/// Exponential backoff with jitter, shared by all exchange connections
+#[derive(Debug, Clone, Copy)]
+pub struct ReconnectPolicy {
+    initial_delay_ms: u64,
+    max_delay_ms: u64,
+    max_attempts: u32,
+}
+
+impl ReconnectPolicy {
+    pub fn from_env() -> Self {
+        Self {
+            initial_delay_ms: get_env_var_or_default(
+                "RECONNECT_INITIAL_DELAY_MS",
+                DEFAULT_RECONNECT_INITIAL_DELAY_MS,
+            ),
+            max_delay_ms: get_env_var_or_default(
+                "RECONNECT_MAX_DELAY_MS",
+                DEFAULT_RECONNECT_MAX_DELAY_MS,
+            ),
+            max_attempts: get_env_var_or_default(
+                "RECONNECT_MAX_ATTEMPTS",
+                DEFAULT_RECONNECT_MAX_ATTEMPTS,
+            ),
+        }
+    }
+
+    /// Delay before reconnection attempt number `attempt` (starting from 0).
+    /// Returns `None` when all attempts are used up.
+    pub fn delay(&self, attempt: u32) -> Option<Duration> {
+        if self.max_attempts != 0 && attempt >= self.max_attempts {
+            return None;
+        }
+
+        let delay_ms = self
+            .initial_delay_ms
+            .saturating_mul(2_u64.saturating_pow(attempt))
+            .min(self.max_delay_ms);
+
+        // Random delay in [delay / 2, delay], so exchanges are not hit by all reconnects at once
+        let delay_ms = rand::thread_rng().gen_range(delay_ms / 2..=delay_ms);
+
+        Some(Duration::from_millis(delay_ms))
+    }
+}
@@ -0,0 +1,40 @@
+use std::time::Duration;
+
+use serde::de::DeserializeOwned;
+use url::Url;
+
+const SNAPSHOT_RETRY_DELAY_MS: u64 = 1000;
+
+/// Fetches REST API snapshot (orderbook, instrument info, etc.) in background, retrying until it succeeds
+/// or the returned receiver is dropped
+pub fn request_snapshot<T>(exchange: &'static str, snapshot_url: Url) -> flume::Receiver<T>
+where
+    T: DeserializeOwned + Send + 'static,
+{
+    let (tx, rx) = flume::bounded(1);
+
+    tokio::spawn(async move {
+        while !tx.is_disconnected() {
+            match fetch_snapshot(snapshot_url.clone()).await {
+                Ok(snapshot) => {
+                    let _ = tx.send(snapshot);
+                    break;
+                }
+                Err(error) => {
+                    println!("Error fetching {} orderbook snapshot: {}", exchange, error);
+                    tokio::time::sleep(Duration::from_millis(SNAPSHOT_RETRY_DELAY_MS)).await;
+                }
+            }
+        }
+    });
+
+    rx
+}
+
+async fn fetch_snapshot<T: DeserializeOwned>(snapshot_url: Url) -> Result<T, reqwest::Error> {
+    reqwest::get(snapshot_url)
+        .await?
+        .error_for_status()?
+        .json::<T>()
+        .await
+}
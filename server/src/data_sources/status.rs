@@ -0,0 +1,59 @@
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    sync::{Arc, RwLock},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConnectionState {
+    #[default]
+    Connecting,
+    Live,
+    BackingOff,
+    /// Reconnection attempts limit is reached, exchange will not be reconnected
+    Failed,
+}
+
+impl Display for ConnectionState {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let state = match self {
+            ConnectionState::Connecting => "connecting",
+            ConnectionState::Live => "live",
+            ConnectionState::BackingOff => "backing off",
+            ConnectionState::Failed => "failed",
+        };
+        write!(f, "{}", state)
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct ExchangeStatus {
+    pub state: ConnectionState,
+    /// Failed connection attempts since the connection was live last time
+    pub reconnect_attempts: u32,
+    /// How many times the exchange stopped sending orderbook updates
+    pub stalls: u64,
+}
+
+/// Connection status of every exchange, keyed by exchange name
+pub type ExchangeStatuses = Arc<RwLock<HashMap<String, ExchangeStatus>>>;
+
+pub fn update_exchange_status<F>(statuses: &ExchangeStatuses, exchange: &str, update: F)
+where
+    F: FnOnce(&mut ExchangeStatus),
+{
+    let mut statuses = statuses
+        .write()
+        .expect("Failed to lock exchange statuses for writing");
+    let status = statuses.entry(exchange.to_string()).or_default();
+    let previous_state = status.state;
+
+    update(status);
+
+    if status.state != previous_state {
+        println!(
+            "{} API connection state: {} -> {}",
+            exchange, previous_state, status.state
+        );
+    }
+}
@@ -0,0 +1,313 @@
+use std::{collections::HashMap, error::Error, fmt};
+
+use crate::helpers::get_env_var_or_default;
+
+// Used to split symbols like `ethbtc` into base and quote assets
+const QUOTE_ASSETS: [&str; 7] = ["USDT", "USDC", "USD", "EUR", "GBP", "BTC", "ETH"];
+
+/// Canonical pairs and exchanges which list them, known without configuration.
+/// Other listings are added with `<EXCHANGE>_PAIRS`, see `PairListings`.
+const LISTED_PAIRS: [(&str, &str, &[&str]); 8] = [
+    (
+        "ETH",
+        "BTC",
+        &[
+            "binance", "bitstamp", "kraken", "coinbase", "okx", "bitfinex",
+        ],
+    ),
+    (
+        "BTC",
+        "USDT",
+        &[
+            "binance", "bitstamp", "kraken", "coinbase", "okx", "bitfinex",
+        ],
+    ),
+    (
+        "ETH",
+        "USDT",
+        &[
+            "binance", "bitstamp", "kraken", "coinbase", "okx", "bitfinex",
+        ],
+    ),
+    (
+        "BTC",
+        "USD",
+        &["bitstamp", "kraken", "coinbase", "bitfinex"],
+    ),
+    (
+        "ETH",
+        "USD",
+        &["bitstamp", "kraken", "coinbase", "bitfinex"],
+    ),
+    (
+        "BTC",
+        "EUR",
+        &["binance", "bitstamp", "kraken", "coinbase", "bitfinex"],
+    ),
+    (
+        "ETH",
+        "EUR",
+        &["binance", "bitstamp", "kraken", "coinbase", "bitfinex"],
+    ),
+    (
+        "LTC",
+        "BTC",
+        &["binance", "bitstamp", "kraken", "okx", "bitfinex"],
+    ),
+];
+
+/// How exchange API names trading pairs
+#[derive(Debug, Clone, Copy)]
+enum SymbolFormat {
+    /// `ethbtc`
+    Lowercase,
+    /// `ETHBTC`
+    Uppercase,
+    /// `ETH-BTC`
+    Dash,
+    /// `ETH/BTC`
+    Slash,
+    /// `tETHBTC`, or `tTESTBTC:TESTUSD` if some asset name is longer than 3 characters
+    Bitfinex,
+}
+
+/// Symbol format and asset names of exchange API.
+/// Assets which are not in `aliases` have the same names as canonical ones.
+struct ExchangeSymbols {
+    api: &'static str,
+    format: SymbolFormat,
+    /// (canonical name, exchange name)
+    aliases: &'static [(&'static str, &'static str)],
+}
+
+/// Kraken REST API uses its own asset names, while WebSocket API v2 uses canonical ones
+pub const KRAKEN_REST_API: &str = "kraken_rest";
+
+const EXCHANGE_SYMBOLS: [ExchangeSymbols; 7] = [
+    ExchangeSymbols {
+        api: "binance",
+        format: SymbolFormat::Lowercase,
+        aliases: &[],
+    },
+    ExchangeSymbols {
+        api: "bitstamp",
+        format: SymbolFormat::Lowercase,
+        aliases: &[],
+    },
+    ExchangeSymbols {
+        api: "kraken",
+        format: SymbolFormat::Slash,
+        aliases: &[],
+    },
+    ExchangeSymbols {
+        api: KRAKEN_REST_API,
+        format: SymbolFormat::Uppercase,
+        aliases: &[("BTC", "XBT"), ("DOGE", "XDG")],
+    },
+    ExchangeSymbols {
+        api: "coinbase",
+        format: SymbolFormat::Dash,
+        aliases: &[],
+    },
+    ExchangeSymbols {
+        api: "okx",
+        format: SymbolFormat::Dash,
+        aliases: &[],
+    },
+    ExchangeSymbols {
+        api: "bitfinex",
+        format: SymbolFormat::Bitfinex,
+        aliases: &[("USDT", "UST"), ("USDC", "UDC")],
+    },
+];
+
+/// Trading pair with canonical uppercase asset names, like ETH/BTC
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Pair {
+    pub base: String,
+    pub quote: String,
+}
+
+impl Pair {
+    /// Parses symbol like `ETH/BTC`, `eth-btc`, `eth_btc` or `ethbtc`.
+    /// Quote asset of the last form is guessed from the list of common quote assets.
+    pub fn parse(symbol: &str) -> Result<Self, Box<dyn Error>> {
+        let symbol = symbol.trim().to_uppercase();
+
+        let assets = match symbol.split_once(['/', '-', '_']) {
+            Some((base, quote)) => Some((base.to_string(), quote.to_string())),
+            None => QUOTE_ASSETS
+                .iter()
+                .find(|quote| symbol.len() > quote.len() && symbol.ends_with(*quote))
+                .map(|quote| {
+                    let base = &symbol[..symbol.len() - quote.len()];
+                    (base.to_string(), quote.to_string())
+                }),
+        };
+
+        match assets {
+            Some((base, quote)) if !base.is_empty() && !quote.is_empty() => {
+                Ok(Self { base, quote })
+            }
+            _ => Err(format!(
+                "Can't split symbol `{}` into base and quote assets, use format like `ETH/BTC`",
+                symbol
+            )
+            .into()),
+        }
+    }
+
+    /// Symbol of this pair in exchange API, like `ETH-BTC` or `tETHBTC`
+    pub fn exchange_symbol(&self, api: &str) -> String {
+        let exchange_symbols = EXCHANGE_SYMBOLS
+            .iter()
+            .find(|exchange_symbols| exchange_symbols.api == api)
+            .unwrap_or_else(|| panic!("Symbol format of {} API is not registered", api));
+
+        let alias = |asset: &str| {
+            exchange_symbols
+                .aliases
+                .iter()
+                .find(|(canonical, _)| *canonical == asset)
+                .map(|(_, alias)| alias.to_string())
+                .unwrap_or_else(|| asset.to_string())
+        };
+        let base = alias(&self.base);
+        let quote = alias(&self.quote);
+
+        match exchange_symbols.format {
+            SymbolFormat::Lowercase => format!("{}{}", base, quote).to_lowercase(),
+            SymbolFormat::Uppercase => format!("{}{}", base, quote),
+            SymbolFormat::Dash => format!("{}-{}", base, quote),
+            SymbolFormat::Slash => format!("{}/{}", base, quote),
+            SymbolFormat::Bitfinex if base.len() > 3 || quote.len() > 3 => {
+                format!("t{}:{}", base, quote)
+            }
+            SymbolFormat::Bitfinex => format!("t{}{}", base, quote),
+        }
+    }
+}
+
+/// Pairs listed on every exchange: the ones from `LISTED_PAIRS`, and extra ones from
+/// `<EXCHANGE>_PAIRS` like `KRAKEN_PAIRS=SOL/USD,ADA/EUR`, so new listings don't need code changes.
+/// Exchange symbols of any pair are derived from the exchange symbol format.
+#[derive(Debug, Default)]
+pub struct PairListings {
+    /// Pairs from `<EXCHANGE>_PAIRS` by exchange name
+    extra_pairs: HashMap<String, Vec<Pair>>,
+}
+
+impl PairListings {
+    /// Fails if some pair of `<EXCHANGE>_PAIRS` can't be parsed
+    pub fn from_env(exchanges: &[&str]) -> Result<Self, Box<dyn Error>> {
+        let mut listings = Self::default();
+
+        for exchange in exchanges {
+            let var_name = format!("{}_PAIRS", exchange.to_uppercase());
+            let symbols = get_env_var_or_default(&var_name, String::new());
+
+            listings
+                .add(exchange, &symbols)
+                .map_err(|error| format!("Invalid {}: {}", var_name, error))?;
+        }
+
+        Ok(listings)
+    }
+
+    /// Lists comma-separated pairs like `SOL/USD,ADA/EUR` on exchange
+    fn add(&mut self, exchange: &str, symbols: &str) -> Result<(), Box<dyn Error>> {
+        for symbol in symbols
+            .split(',')
+            .filter(|symbol| !symbol.trim().is_empty())
+        {
+            let pair = Pair::parse(symbol)?;
+            self.extra_pairs
+                .entry(exchange.to_string())
+                .or_default()
+                .push(pair);
+        }
+
+        Ok(())
+    }
+
+    pub fn is_listed(&self, pair: &Pair, exchange: &str) -> bool {
+        let is_listed_by_default = LISTED_PAIRS.iter().any(|(base, quote, exchanges)| {
+            *base == pair.base && *quote == pair.quote && exchanges.contains(&exchange)
+        });
+        let is_listed_by_config = self
+            .extra_pairs
+            .get(exchange)
+            .is_some_and(|pairs| pairs.contains(pair));
+
+        is_listed_by_default || is_listed_by_config
+    }
+}
+
+impl fmt::Display for Pair {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}/{}", self.base, self.quote)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pair(symbol: &str) -> Pair {
+        Pair::parse(symbol).unwrap()
+    }
+
+    #[test]
+    fn parses_symbols() {
+        for symbol in ["ETH/BTC", "eth-btc", "eth_btc", "ethbtc", " ETHBTC "] {
+            assert_eq!(pair(symbol).to_string(), "ETH/BTC", "{}", symbol);
+        }
+        assert_eq!(pair("btcusdt").to_string(), "BTC/USDT");
+        assert_eq!(pair("btcusd").to_string(), "BTC/USD");
+        assert_eq!(pair("sol/usd").to_string(), "SOL/USD");
+
+        for invalid in ["eth", "usdt", "eth/", "-btc", ""] {
+            assert!(Pair::parse(invalid).is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn translates_to_exchange_symbols() {
+        let eth_btc = pair("ETH/BTC");
+
+        assert_eq!(eth_btc.exchange_symbol("binance"), "ethbtc");
+        assert_eq!(eth_btc.exchange_symbol("bitstamp"), "ethbtc");
+        assert_eq!(eth_btc.exchange_symbol("coinbase"), "ETH-BTC");
+        assert_eq!(eth_btc.exchange_symbol("okx"), "ETH-BTC");
+        assert_eq!(eth_btc.exchange_symbol("bitfinex"), "tETHBTC");
+        assert_eq!(eth_btc.exchange_symbol("kraken"), "ETH/BTC");
+        assert_eq!(eth_btc.exchange_symbol(KRAKEN_REST_API), "ETHXBT");
+    }
+
+    #[test]
+    fn translates_asset_aliases() {
+        assert_eq!(pair("BTC/USDT").exchange_symbol("bitfinex"), "tBTCUST");
+        assert_eq!(pair("ETH/USDC").exchange_symbol("bitfinex"), "tETHUDC");
+        // Names longer than 3 characters are separated by colon
+        assert_eq!(pair("DOGE/USD").exchange_symbol("bitfinex"), "tDOGE:USD");
+
+        assert_eq!(pair("DOGE/USD").exchange_symbol(KRAKEN_REST_API), "XDGUSD");
+        // WebSocket API v2 uses canonical names
+        assert_eq!(pair("DOGE/BTC").exchange_symbol("kraken"), "DOGE/BTC");
+    }
+
+    #[test]
+    fn lists_built_in_and_configured_pairs() {
+        let mut listings = PairListings::default();
+        assert!(listings.is_listed(&pair("ethbtc"), "kraken"));
+        assert!(!listings.is_listed(&pair("btcusd"), "binance"));
+        assert!(!listings.is_listed(&pair("solusd"), "kraken"));
+
+        listings.add("kraken", "SOL/USD, adaeur,").unwrap();
+        assert!(listings.is_listed(&pair("solusd"), "kraken"));
+        assert!(listings.is_listed(&pair("ADA-EUR"), "kraken"));
+        assert!(!listings.is_listed(&pair("solusd"), "binance"));
+
+        assert!(listings.add("kraken", "SOL").is_err());
+    }
+}
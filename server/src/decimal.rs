@@ -0,0 +1,349 @@
+use std::{
+    error::Error,
+    fmt,
+    iter::Sum,
+    ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign},
+    str::FromStr,
+};
+
+use serde::{de, Deserialize, Deserializer};
+
+/// Enough for the smallest prices and amounts of all supported exchanges
+const DECIMAL_PLACES: usize = 18;
+const SCALE: i128 = 10i128.pow(DECIMAL_PLACES as u32);
+
+/// Fixed-point decimal number with 18 decimal places, so prices and amounts are exactly
+/// as exchanges send them: equal prices are always equal, and sums don't lose precision.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Decimal(i128);
+
+impl Decimal {
+    pub const ZERO: Decimal = Decimal(0);
+    pub const ONE: Decimal = Decimal(SCALE);
+
+    pub fn is_zero(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn is_positive(self) -> bool {
+        self.0 > 0
+    }
+
+    pub fn is_negative(self) -> bool {
+        self.0 < 0
+    }
+
+    pub fn abs(self) -> Decimal {
+        Decimal(self.0.abs())
+    }
+
+    /// The biggest multiple of `step` which is not greater than this number
+    pub fn round_down_to(self, step: Decimal) -> Decimal {
+        Decimal(self.0.div_euclid(step.0) * step.0)
+    }
+
+    /// The smallest multiple of `step` which is not less than this number
+    pub fn round_up_to(self, step: Decimal) -> Decimal {
+        let rounded_down = self.round_down_to(step);
+        if rounded_down == self {
+            rounded_down
+        } else {
+            rounded_down + step
+        }
+    }
+
+    /// Nearest `f64`, for APIs which use floating point numbers
+    pub fn to_f64(self) -> f64 {
+        self.to_string()
+            .parse()
+            .expect("Failed to convert decimal to f64")
+    }
+}
+
+impl FromStr for Decimal {
+    type Err = Box<dyn Error + Send + Sync>;
+
+    /// Parses numbers like `123`, `-0.05` or `0.00001000`.
+    /// Digits beyond 18 decimal places are dropped.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("Invalid decimal number `{}`", s);
+
+        let (is_negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (integer, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+
+        let is_valid = !(integer.is_empty() && fraction.is_empty())
+            && integer
+                .chars()
+                .chain(fraction.chars())
+                .all(|c| c.is_ascii_digit());
+        if !is_valid {
+            return Err(invalid().into());
+        }
+
+        let integer: i128 = if integer.is_empty() {
+            0
+        } else {
+            integer.parse()?
+        };
+
+        let fraction = &fraction[..fraction.len().min(DECIMAL_PLACES)];
+        let fraction: i128 = format!("{:0<width$}", fraction, width = DECIMAL_PLACES).parse()?;
+
+        let value = integer
+            .checked_mul(SCALE)
+            .and_then(|value| value.checked_add(fraction))
+            .ok_or_else(invalid)?;
+
+        Ok(Decimal(if is_negative { -value } else { value }))
+    }
+}
+
+impl TryFrom<f64> for Decimal {
+    type Error = Box<dyn Error + Send + Sync>;
+
+    /// Uses the shortest representation of the number, like `0.1` for `0.1_f64`,
+    /// which is how exchanges with JSON numbers send it
+    fn try_from(value: f64) -> Result<Self, Self::Error> {
+        if !value.is_finite() {
+            return Err(format!("Invalid decimal number `{}`", value).into());
+        }
+
+        value.to_string().parse()
+    }
+}
+
+/// Without trailing zeros. If precision is specified, like in `{:.2}`,
+/// the number has exactly that many decimal places, extra ones are dropped.
+impl fmt::Display for Decimal {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let sign = if self.is_negative() { "-" } else { "" };
+        let integer = self.0.abs() / SCALE;
+        let fraction = format!("{:0width$}", self.0.abs() % SCALE, width = DECIMAL_PLACES);
+
+        let fraction = match f.precision() {
+            Some(precision) => format!("{:0<width$.width$}", fraction, width = precision),
+            None => fraction.trim_end_matches('0').to_string(),
+        };
+
+        if fraction.is_empty() {
+            write!(f, "{}{}", sign, integer)
+        } else {
+            write!(f, "{}{}.{}", sign, integer, fraction)
+        }
+    }
+}
+
+/// Accepts both strings and JSON numbers
+impl<'de> Deserialize<'de> for Decimal {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct DecimalVisitor;
+
+        impl<'de> de::Visitor<'de> for DecimalVisitor {
+            type Value = Decimal;
+
+            fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                formatter.write_str("decimal number as string or number")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Decimal, E> {
+                value.parse().map_err(E::custom)
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<Decimal, E> {
+                Ok(Decimal(value as i128 * SCALE))
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<Decimal, E> {
+                Ok(Decimal(value as i128 * SCALE))
+            }
+
+            fn visit_f64<E: de::Error>(self, value: f64) -> Result<Decimal, E> {
+                Decimal::try_from(value).map_err(E::custom)
+            }
+        }
+
+        deserializer.deserialize_any(DecimalVisitor)
+    }
+}
+
+impl Add for Decimal {
+    type Output = Decimal;
+
+    fn add(self, other: Decimal) -> Decimal {
+        Decimal(self.0 + other.0)
+    }
+}
+
+impl AddAssign for Decimal {
+    fn add_assign(&mut self, other: Decimal) {
+        self.0 += other.0;
+    }
+}
+
+impl Sub for Decimal {
+    type Output = Decimal;
+
+    fn sub(self, other: Decimal) -> Decimal {
+        Decimal(self.0 - other.0)
+    }
+}
+
+impl SubAssign for Decimal {
+    fn sub_assign(&mut self, other: Decimal) {
+        self.0 -= other.0;
+    }
+}
+
+/// Extra decimal places of the product are dropped
+impl Mul for Decimal {
+    type Output = Decimal;
+
+    fn mul(self, other: Decimal) -> Decimal {
+        // Integer and fractional parts are multiplied separately, so big numbers don't overflow
+        let (a_integer, a_fraction) = (self.0 / SCALE, self.0 % SCALE);
+        let (b_integer, b_fraction) = (other.0 / SCALE, other.0 % SCALE);
+
+        Decimal(
+            a_integer * b_integer * SCALE
+                + a_integer * b_fraction
+                + a_fraction * b_integer
+                + a_fraction * b_fraction / SCALE,
+        )
+    }
+}
+
+/// Extra decimal places of the quotient are dropped. Panics on division by zero.
+impl Div for Decimal {
+    type Output = Decimal;
+
+    fn div(self, other: Decimal) -> Decimal {
+        let (dividend, divisor) = (self.0.abs(), other.0.abs());
+
+        // Long division, so the dividend is never scaled up and big numbers don't overflow
+        let mut quotient = dividend / divisor * SCALE;
+        let mut remainder = dividend % divisor;
+        let mut digit_scale = SCALE;
+        while digit_scale > 1 && remainder != 0 {
+            digit_scale /= 10;
+            remainder *= 10;
+            quotient += remainder / divisor * digit_scale;
+            remainder %= divisor;
+        }
+
+        if self.is_negative() != other.is_negative() {
+            Decimal(-quotient)
+        } else {
+            Decimal(quotient)
+        }
+    }
+}
+
+impl Neg for Decimal {
+    type Output = Decimal;
+
+    fn neg(self) -> Decimal {
+        Decimal(-self.0)
+    }
+}
+
+impl Sum for Decimal {
+    fn sum<I: Iterator<Item = Decimal>>(iter: I) -> Decimal {
+        iter.fold(Decimal::ZERO, |sum, value| sum + value)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decimal(s: &str) -> Decimal {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn parses_and_displays() {
+        for (input, output) in [
+            ("123", "123"),
+            ("-0.5", "-0.5"),
+            (".5", "0.5"),
+            ("+1.50", "1.5"),
+            ("0.00001000", "0.00001"),
+            ("-0", "0"),
+            ("0.000000000000000001", "0.000000000000000001"),
+            // Digits beyond 18 decimal places are dropped
+            ("1.1234567890123456789", "1.123456789012345678"),
+        ] {
+            assert_eq!(decimal(input).to_string(), output, "{}", input);
+        }
+
+        for invalid in ["", "-", ".", "1.2.3", "1e-7", "abc", " 1"] {
+            assert!(invalid.parse::<Decimal>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn displays_with_precision() {
+        assert_eq!(format!("{:.5}", decimal("0.05")), "0.05000");
+        // Extra decimal places are dropped, not rounded
+        assert_eq!(format!("{:.2}", decimal("1.239")), "1.23");
+        assert_eq!(format!("{:.0}", decimal("-7.9")), "-7");
+    }
+
+    #[test]
+    fn multiplies() {
+        assert_eq!(decimal("0.5") * decimal("3"), decimal("1.5"));
+        assert_eq!(decimal("-0.5") * decimal("3"), decimal("-1.5"));
+        assert_eq!(decimal("-0.5") * decimal("-0.5"), decimal("0.25"));
+        assert_eq!(decimal("-2.5") * decimal("0.002"), decimal("-0.005"));
+        assert_eq!(
+            decimal("65000.12") * decimal("1000.5"),
+            decimal("65032620.06")
+        );
+    }
+
+    #[test]
+    fn divides() {
+        assert_eq!(decimal("1") / decimal("4"), decimal("0.25"));
+        assert_eq!(decimal("-1") / decimal("4"), decimal("-0.25"));
+        assert_eq!(decimal("1") / decimal("-4"), decimal("-0.25"));
+        assert_eq!(decimal("-1.5") / decimal("-0.5"), decimal("3"));
+        assert_eq!(decimal("1") / decimal("3"), decimal("0.333333333333333333"));
+        assert_eq!(
+            decimal("65032620.06") / decimal("1000.5"),
+            decimal("65000.12")
+        );
+    }
+
+    #[test]
+    #[should_panic]
+    fn panics_on_division_by_zero() {
+        let _ = decimal("1") / Decimal::ZERO;
+    }
+
+    #[test]
+    fn rounds_to_step() {
+        let step = decimal("0.002");
+
+        // Exact multiples are kept
+        assert_eq!(decimal("0.048").round_down_to(step), decimal("0.048"));
+        assert_eq!(decimal("0.048").round_up_to(step), decimal("0.048"));
+
+        assert_eq!(decimal("0.049").round_down_to(step), decimal("0.048"));
+        assert_eq!(decimal("0.0505").round_up_to(step), decimal("0.052"));
+        assert_eq!(decimal("-0.001").round_down_to(step), decimal("-0.002"));
+        assert_eq!(decimal("-0.001").round_up_to(step), decimal("0"));
+    }
+
+    #[test]
+    fn converts_f64() {
+        assert_eq!(Decimal::try_from(1e-7).unwrap(), decimal("0.0000001"));
+        assert_eq!(Decimal::try_from(0.1).unwrap(), decimal("0.1"));
+        assert!(Decimal::try_from(f64::NAN).is_err());
+
+        assert_eq!(decimal("0.0000001").to_f64(), 1e-7);
+        assert_eq!(decimal("0.1").to_f64(), 0.1);
+    }
+}
@@ -1,7 +1,9 @@
 use std::{
     env,
+    error::Error,
     fmt::{Debug, Display},
     str::FromStr,
+    time::{SystemTime, UNIX_EPOCH},
 };
 
 pub fn get_env_var_or_default<T>(var_name: &str, default: T) -> T
@@ -17,3 +19,86 @@ where
     println!("{}: {}", var_name, value);
     value
 }
+
+/// Unix time in milliseconds
+pub fn get_current_timestamp_ms() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Failed to get current time")
+        .as_millis() as u64
+}
+
+/// Unix time in milliseconds of UTC time like `2023-10-06T17:35:55.440295Z`, as exchanges send it.
+/// Digits beyond milliseconds are dropped.
+pub fn parse_utc_timestamp_ms(timestamp: &str) -> Result<u64, Box<dyn Error + Send + Sync>> {
+    let invalid = || format!("Invalid UTC timestamp `{}`", timestamp);
+
+    let (date, time) = timestamp
+        .strip_suffix('Z')
+        .and_then(|timestamp| timestamp.split_once('T'))
+        .ok_or_else(invalid)?;
+    let (time, fraction) = time.split_once('.').unwrap_or((time, ""));
+
+    let parse_parts = |text: &str, separator: char| -> Result<[i64; 3], String> {
+        let parts = text
+            .split(separator)
+            .map(|part| part.parse::<i64>().map_err(|_| invalid()))
+            .collect::<Result<Vec<i64>, String>>()?;
+        parts.try_into().map_err(|_| invalid())
+    };
+    let [year, month, day] = parse_parts(date, '-')?;
+    let [hours, minutes, seconds] = parse_parts(time, ':')?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(invalid().into());
+    }
+
+    let milliseconds: i64 = if fraction.is_empty() {
+        0
+    } else {
+        format!("{:0<3.3}", fraction)
+            .parse()
+            .map_err(|_| invalid())?
+    };
+
+    // Days since 1970-01-01, from http://howardhinnant.github.io/date_algorithms.html#days_from_civil
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+
+    let seconds = days * 86400 + hours * 3600 + minutes * 60 + seconds;
+    u64::try_from(seconds * 1000 + milliseconds).map_err(|_| invalid().into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_utc_timestamps() {
+        assert_eq!(parse_utc_timestamp_ms("1970-01-01T00:00:00Z").unwrap(), 0);
+        assert_eq!(
+            parse_utc_timestamp_ms("2023-10-06T17:35:55.440295Z").unwrap(),
+            1696613755440
+        );
+        assert_eq!(
+            parse_utc_timestamp_ms("2023-02-09T20:32:50.714964855Z").unwrap(),
+            1675974770714
+        );
+        assert_eq!(
+            parse_utc_timestamp_ms("2024-02-29T00:00:00.5Z").unwrap(),
+            1709164800500
+        );
+
+        for invalid in [
+            "",
+            "2023-10-06",
+            "2023-10-06T17:35:55",
+            "2023-13-06T17:35:55Z",
+        ] {
+            assert!(parse_utc_timestamp_ms(invalid).is_err(), "{}", invalid);
+        }
+    }
+}
@@ -1,5 +1,6 @@
 mod api;
 mod data_sources;
+mod decimal;
 mod summary;
 
 mod helpers;
@@ -12,13 +13,46 @@ const DEFAULT_DATA_LIFETIME_MS: u64 = 2000; // 2 seconds
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let symbol = get_env_var_or_default("SYMBOL", DEFAULT_SYMBOL.to_string());
+    // Comma-separated list, like `ethbtc,btcusdt`. Only `SYMBOL` is aggregated if it's not set.
+    let symbols = get_env_var_or_default("SYMBOLS", symbol);
+    let symbols: Vec<String> = symbols
+        .split(',')
+        .map(|symbol| symbol.trim().to_string())
+        .filter(|symbol| !symbol.is_empty())
+        .collect();
     let depth: u16 = get_env_var_or_default("DEPTH", DEFAULT_DEPTH);
     let data_lifetime_ms = get_env_var_or_default("DATA_LIFETIME_MS", DEFAULT_DATA_LIFETIME_MS);
 
-    let data_rx = data_sources::get_data_rx(symbol, depth);
-    let summary_rx = summary::get_summary_rx(data_rx, depth, data_lifetime_ms);
+    // Connection state of every exchange, updated by data sources
+    let exchange_statuses = data_sources::status::ExchangeStatuses::default();
 
-    api::serve(summary_rx).await?;
+    let data_rxs = data_sources::get_data_rxs(&symbols, depth, exchange_statuses.clone())?;
+
+    let mut exchanges: Vec<&'static str> = vec![];
+    for data_rx in data_rxs.iter() {
+        for exchange in data_rx.exchanges.iter() {
+            if !exchanges.contains(exchange) {
+                exchanges.push(exchange);
+            }
+        }
+    }
+    let fee_schedules = summary::get_fee_schedules(&exchanges)?;
+
+    // Every symbol has its own orderbooks state, summaries are calculated from it for every client
+    let orderbooks_rxs = data_rxs
+        .into_iter()
+        .map(|data_rx| {
+            let orderbooks_rx = summary::get_orderbooks_rx(
+                data_rx.data_rx,
+                data_rx.exchanges,
+                exchange_statuses.clone(),
+                fee_schedules.clone(),
+            );
+            (data_rx.pair, orderbooks_rx)
+        })
+        .collect();
+
+    api::serve(orderbooks_rxs, depth, data_lifetime_ms).await?;
 
     Ok(())
 }
@@ -1,94 +1,519 @@
-use std::{
-    collections::HashMap,
-    time::{SystemTime, UNIX_EPOCH},
+use crate::api::orderbook::{
+    ConnectionState, ExchangeAmount, ExchangeStatus, ExclusionReason, Level, SourceTimestamps,
+    Summary,
 };
+use crate::data_sources::status;
+use crate::decimal::Decimal;
+use crate::helpers::get_current_timestamp_ms;
 
-use crate::api::orderbook::{Level, Summary};
-use crate::data_sources::output_data_format::ExchangeOrderbookData;
+use super::{PairOrderbooks, SummaryView};
 
 pub fn calculate_summary(
-    orderbook_data: HashMap<String, ExchangeOrderbookData>,
-    depth: u16,
+    pair_orderbooks: &PairOrderbooks,
+    view: &SummaryView,
     data_lifetime_ms: u64,
 ) -> Option<Summary> {
-    let mut bids: Vec<Level> = Vec::new();
-    let mut asks: Vec<Level> = Vec::new();
+    let depth = view.depth;
+    let current_timestamp = get_current_timestamp_ms();
 
-    for (exchange, orderbook) in orderbook_data.iter() {
-        let current_timestamp = SystemTime::now()
-            .duration_since(UNIX_EPOCH)
-            .expect("Failed to get current time")
-            .as_millis();
-        let data_age = current_timestamp as u64 - orderbook.timestamp;
+    let AggregatedLevels {
+        mut bids,
+        mut asks,
+        data_age_ms,
+        sources,
+        exchange_statuses,
+    } = aggregate_levels(pair_orderbooks, view, data_lifetime_ms, current_timestamp);
 
-        // println!("[DEBUG] {} data age: {}; ", exchange, data_age);
+    if view.merge_levels {
+        bids = merge_levels(bids);
+        asks = merge_levels(asks);
+    }
 
-        if data_age > data_lifetime_ms {
-            // Data is too old, skip it
-            continue;
+    // Select only first `depth` levels
+    {
+        if bids.len() > depth as usize {
+            bids.truncate(depth as usize);
         }
 
-        let mut exchange_bids: Vec<Level> = orderbook
-            .bids
-            .iter()
-            .map(|(price, amount)| Level {
-                exchange: exchange.to_string(),
-                price: *price,
-                amount: *amount,
-            })
-            .collect();
-        bids.append(&mut exchange_bids);
+        if asks.len() > depth as usize {
+            asks.truncate(depth as usize);
+        }
+    }
 
-        let mut exchange_asks: Vec<Level> = orderbook
-            .asks
-            .iter()
-            .map(|(price, amount)| Level {
-                exchange: exchange.to_string(),
-                price: *price,
-                amount: *amount,
-            })
-            .collect();
+    if asks.is_empty() && bids.is_empty() {
+        return None;
+    }
+
+    // One side can be empty, for example in a thin market or if only one side is updated yet
+    let (spread, crossed) = match (asks.first(), bids.first()) {
+        (Some(best_ask), Some(best_bid)) => (
+            Some(best_ask.price - best_bid.price),
+            best_bid.price >= best_ask.price,
+        ),
+        _ => (None, false),
+    };
+    let effective_spread = match (asks.first(), bids.first()) {
+        (Some(best_ask), Some(best_bid)) if view.fee_adjusted => {
+            Some(best_ask.ranking_price() - best_bid.ranking_price())
+        }
+        _ => None,
+    };
+
+    Some(Summary {
+        spread: spread.map(|spread| spread.to_f64()),
+        bids: bids.into_iter().map(DecimalLevel::into_proto).collect(),
+        asks: asks.into_iter().map(DecimalLevel::into_proto).collect(),
+        data_age_ms,
+        cached: false,
+        sequence: pair_orderbooks.sequence,
+        timestamp: current_timestamp,
+        sources,
+        exchange_statuses,
+        crossed,
+        exact_spread: spread.map(|spread| spread.to_string()),
+        effective_spread: effective_spread.map(|spread| spread.to_f64()),
+        exact_effective_spread: effective_spread.map(|spread| spread.to_string()),
+    })
+}
+
+/// Levels of every exchange used by the view, with fresh data, best first. Levels are not merged.
+pub(super) struct AggregatedLevels {
+    pub bids: Vec<DecimalLevel>,
+    pub asks: Vec<DecimalLevel>,
+    /// Age of the freshest exchange data
+    pub data_age_ms: u64,
+    pub sources: Vec<SourceTimestamps>,
+    /// Status of every exchange of the pair, including the ones without levels
+    pub exchange_statuses: Vec<ExchangeStatus>,
+}
+
+pub(super) fn aggregate_levels(
+    pair_orderbooks: &PairOrderbooks,
+    view: &SummaryView,
+    data_lifetime_ms: u64,
+    current_timestamp: u64,
+) -> AggregatedLevels {
+    let mut bids: Vec<DecimalLevel> = Vec::new();
+    let mut asks: Vec<DecimalLevel> = Vec::new();
+    let mut data_age_ms = u64::MAX;
+    let mut sources: Vec<SourceTimestamps> = Vec::new();
+    let mut exchange_statuses: Vec<ExchangeStatus> = Vec::new();
+
+    let connection_statuses = pair_orderbooks
+        .exchange_statuses
+        .read()
+        .expect("Failed to lock exchange statuses for reading")
+        .clone();
+
+    for exchange in pair_orderbooks.exchanges.iter() {
+        let orderbook = pair_orderbooks.orderbooks.get(*exchange);
+        // Exchange clock can be a bit ahead of ours
+        let data_age =
+            orderbook.map(|orderbook| current_timestamp.saturating_sub(orderbook.timestamp));
+
+        let exclusion_reason = match data_age {
+            _ if !view.uses_exchange(exchange) => ExclusionReason::Filtered,
+            None => ExclusionReason::NoData,
+            Some(data_age) if data_age > data_lifetime_ms => ExclusionReason::StaleData,
+            Some(_) => ExclusionReason::NotExcluded,
+        };
+
+        let connection_status = connection_statuses
+            .get(*exchange)
+            .cloned()
+            .unwrap_or_default();
+        let fee_schedule = pair_orderbooks
+            .fee_schedules
+            .get(*exchange)
+            .copied()
+            .unwrap_or_default();
+
+        exchange_statuses.push(ExchangeStatus {
+            exchange: exchange.to_string(),
+            state: to_proto_connection_state(connection_status.state) as i32,
+            data_age_ms: data_age,
+            included: exclusion_reason == ExclusionReason::NotExcluded,
+            exclusion_reason: exclusion_reason as i32,
+            maker_fee: fee_schedule.maker.to_string(),
+            taker_fee: fee_schedule.taker.to_string(),
+            stalls: connection_status.stalls,
+        });
+
+        let (orderbook, data_age) = match (orderbook, data_age) {
+            (Some(orderbook), Some(data_age))
+                if exclusion_reason == ExclusionReason::NotExcluded =>
+            {
+                (orderbook, data_age)
+            }
+            _ => continue,
+        };
+
+        data_age_ms = data_age_ms.min(data_age);
+        sources.push(SourceTimestamps {
+            exchange: exchange.to_string(),
+            exchange_timestamp: orderbook.timestamp,
+            received_timestamp: orderbook.received_timestamp,
+        });
+
+        let mut exchange_bids = get_exchange_levels(
+            exchange,
+            &orderbook.bids,
+            view.tick_size,
+            false,
+            orderbook.are_bids_trimmed(),
+        );
+        let mut exchange_asks = get_exchange_levels(
+            exchange,
+            &orderbook.asks,
+            view.tick_size,
+            true,
+            orderbook.are_asks_trimmed(),
+        );
+
+        if view.fee_adjusted {
+            for level in exchange_bids.iter_mut() {
+                level.effective_price = Some(fee_schedule.effective_bid_price(level.price));
+            }
+            for level in exchange_asks.iter_mut() {
+                level.effective_price = Some(fee_schedule.effective_ask_price(level.price));
+            }
+        }
+
+        bids.append(&mut exchange_bids);
         asks.append(&mut exchange_asks);
     }
 
     // sort bids and asks by price and amount
     bids.sort_by(|a, b| {
-        b.price
-            .partial_cmp(&a.price)
-            .expect("Failed to compare bid prices")
-            .then(
-                b.amount
-                    .partial_cmp(&a.amount)
-                    .expect("Failed to compare bid amounts"),
-            )
+        b.ranking_price()
+            .cmp(&a.ranking_price())
+            .then(b.amount.cmp(&a.amount))
     });
     asks.sort_by(|a, b| {
-        a.price
-            .partial_cmp(&b.price)
-            .expect("Failed to compare ask prices")
-            .then(
-                b.amount
-                    .partial_cmp(&a.amount)
-                    .expect("Failed to compare ask amounts"),
-            )
+        a.ranking_price()
+            .cmp(&b.ranking_price())
+            .then(b.amount.cmp(&a.amount))
     });
 
-    // Select only first `depth` levels
-    {
-        if bids.len() > depth as usize {
-            bids.truncate(depth as usize);
+    AggregatedLevels {
+        bids,
+        asks,
+        data_age_ms,
+        sources,
+        exchange_statuses,
+    }
+}
+
+/// Level with exact price and amount, which is converted to the API format when summary is ready
+#[derive(Debug)]
+pub(super) struct DecimalLevel {
+    pub exchange: String,
+    pub price: Decimal,
+    /// Price after taker fee, for fee-adjusted summaries only
+    pub effective_price: Option<Decimal>,
+    pub amount: Decimal,
+    /// Exchanges and their amounts, for merged levels only
+    contributions: Vec<(String, Decimal)>,
+}
+
+impl DecimalLevel {
+    /// Price by which levels are sorted
+    pub fn ranking_price(&self) -> Decimal {
+        self.effective_price.unwrap_or(self.price)
+    }
+
+    fn into_proto(self) -> Level {
+        Level {
+            exchange: self.exchange,
+            price: self.price.to_f64(),
+            amount: self.amount.to_f64(),
+            contributions: self
+                .contributions
+                .into_iter()
+                .map(|(exchange, amount)| ExchangeAmount {
+                    exchange,
+                    amount: amount.to_f64(),
+                    exact_amount: amount.to_string(),
+                })
+                .collect(),
+            exact_price: self.price.to_string(),
+            exact_amount: self.amount.to_string(),
+            effective_price: self.effective_price.map(|price| price.to_f64()),
+            exact_effective_price: self.effective_price.map(|price| price.to_string()),
         }
+    }
+}
 
-        if asks.len() > depth as usize {
-            asks.truncate(depth as usize);
+/// Levels of a single exchange. If `tick_size` is set, prices are rounded to it
+/// (up for asks and down for bids), and levels in the same bucket are summed up.
+/// Exchange levels are sorted by price, so levels of a bucket are adjacent.
+/// If the side is trimmed, the last bucket can miss levels beyond the data, so it is dropped,
+/// unless it is the only one.
+fn get_exchange_levels(
+    exchange: &str,
+    price_amounts: &[(Decimal, Decimal)],
+    tick_size: Decimal,
+    round_up: bool,
+    is_trimmed: bool,
+) -> Vec<DecimalLevel> {
+    let mut levels: Vec<DecimalLevel> = Vec::new();
+
+    for (price, amount) in price_amounts {
+        let price = match (tick_size.is_positive(), round_up) {
+            (false, _) => *price,
+            (true, true) => price.round_up_to(tick_size),
+            (true, false) => price.round_down_to(tick_size),
+        };
+
+        match levels.last_mut() {
+            Some(level) if level.price == price => level.amount += *amount,
+            _ => levels.push(DecimalLevel {
+                exchange: exchange.to_string(),
+                price,
+                effective_price: None,
+                amount: *amount,
+                contributions: Vec::new(),
+            }),
         }
     }
 
-    if asks.is_empty() && bids.is_empty() {
-        return None;
+    if tick_size.is_positive() && is_trimmed && levels.len() > 1 {
+        levels.pop();
     }
 
-    let spread = asks[0].price - bids[0].price;
+    levels
+}
+
+/// Merges adjacent levels with the same price, so levels must be sorted by price.
+/// In fee-adjusted summaries, effective prices must be the same too.
+/// Exchanges of merged level keep the order of levels, which are sorted by amount.
+fn merge_levels(levels: Vec<DecimalLevel>) -> Vec<DecimalLevel> {
+    let mut merged_levels: Vec<DecimalLevel> = Vec::new();
+
+    for level in levels {
+        let contribution = (level.exchange.clone(), level.amount);
+
+        match merged_levels.last_mut() {
+            Some(merged_level)
+                if merged_level.price == level.price
+                    && merged_level.effective_price == level.effective_price =>
+            {
+                merged_level.exchange = format!("{},{}", merged_level.exchange, level.exchange);
+                merged_level.amount += level.amount;
+                merged_level.contributions.push(contribution);
+            }
+            _ => merged_levels.push(DecimalLevel {
+                contributions: vec![contribution],
+                ..level
+            }),
+        }
+    }
+
+    merged_levels
+}
+
+fn to_proto_connection_state(state: status::ConnectionState) -> ConnectionState {
+    match state {
+        status::ConnectionState::Connecting => ConnectionState::Connecting,
+        status::ConnectionState::Live => ConnectionState::Live,
+        status::ConnectionState::BackingOff => ConnectionState::BackingOff,
+        status::ConnectionState::Failed => ConnectionState::Failed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, RwLock};
+
+    use crate::data_sources::output_data_format::ExchangeOrderbookData;
+
+    use super::*;
+
+    fn price_amounts(levels: &[(&str, &str)]) -> Vec<(Decimal, Decimal)> {
+        levels
+            .iter()
+            .map(|(price, amount)| (price.parse().unwrap(), amount.parse().unwrap()))
+            .collect()
+    }
+
+    fn level(exchange: &str, price: &str, amount: &str) -> DecimalLevel {
+        DecimalLevel {
+            exchange: exchange.to_string(),
+            price: price.parse().unwrap(),
+            effective_price: None,
+            amount: amount.parse().unwrap(),
+            contributions: Vec::new(),
+        }
+    }
+
+    /// `price amount` of every level
+    fn format_levels(levels: &[DecimalLevel]) -> Vec<String> {
+        levels
+            .iter()
+            .map(|level| format!("{} {}", level.price, level.amount))
+            .collect()
+    }
+
+    fn view(merge_levels: bool, tick_size: &str) -> SummaryView {
+        SummaryView {
+            depth: 10,
+            include_exchanges: Vec::new(),
+            exclude_exchanges: Vec::new(),
+            merge_levels,
+            tick_size: tick_size.parse().unwrap(),
+            fee_adjusted: false,
+        }
+    }
+
+    fn pair_orderbooks(orderbooks: Vec<ExchangeOrderbookData>) -> PairOrderbooks {
+        PairOrderbooks {
+            sequence: 1,
+            exchanges: vec!["binance", "kraken"],
+            orderbooks: orderbooks
+                .into_iter()
+                .map(|orderbook| (orderbook.exchange.clone(), orderbook))
+                .collect(),
+            exchange_statuses: Arc::new(RwLock::new(HashMap::new())),
+            fee_schedules: Arc::new(HashMap::new()),
+        }
+    }
+
+    #[test]
+    fn keeps_exchange_levels_without_tick_size() {
+        let bids = price_amounts(&[("0.0495", "1"), ("0.0491", "2")]);
+
+        let levels = get_exchange_levels("binance", &bids, Decimal::ZERO, false, true);
+
+        assert_eq!(format_levels(&levels), vec!["0.0495 1", "0.0491 2"]);
+    }
 
-    Some(Summary { spread, bids, asks })
+    #[test]
+    fn buckets_bids_down_and_asks_up() {
+        let tick_size = "0.001".parse().unwrap();
+        let bids = price_amounts(&[("0.0495", "1"), ("0.0491", "2"), ("0.0489", "3")]);
+        let asks = price_amounts(&[("0.0501", "1"), ("0.0509", "2"), ("0.051", "3")]);
+
+        let bid_levels = get_exchange_levels("binance", &bids, tick_size, false, false);
+        let ask_levels = get_exchange_levels("binance", &asks, tick_size, true, false);
+
+        assert_eq!(format_levels(&bid_levels), vec!["0.049 3", "0.048 3"]);
+        assert_eq!(format_levels(&ask_levels), vec!["0.051 6"]);
+    }
+
+    #[test]
+    fn drops_last_bucket_of_trimmed_side() {
+        let tick_size = "0.001".parse().unwrap();
+        let bids = price_amounts(&[("0.0495", "1"), ("0.0491", "2"), ("0.0489", "3")]);
+
+        let levels = get_exchange_levels("binance", &bids, tick_size, false, true);
+
+        assert_eq!(format_levels(&levels), vec!["0.049 3"]);
+    }
+
+    #[test]
+    fn keeps_only_bucket_of_trimmed_side() {
+        let tick_size = "0.01".parse().unwrap();
+        let bids = price_amounts(&[("0.0495", "1"), ("0.0491", "2")]);
+
+        let levels = get_exchange_levels("binance", &bids, tick_size, false, true);
+
+        assert_eq!(format_levels(&levels), vec!["0.04 3"]);
+    }
+
+    #[test]
+    fn merges_levels_with_the_same_price() {
+        let levels = vec![
+            level("binance", "0.05", "2"),
+            level("kraken", "0.05", "1"),
+            level("bitstamp", "0.05", "0.5"),
+            level("kraken", "0.049", "4"),
+        ];
+
+        let merged = merge_levels(levels);
+
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].exchange, "binance,kraken,bitstamp");
+        assert_eq!(merged[0].amount, "3.5".parse().unwrap());
+        assert_eq!(
+            merged[0].contributions,
+            vec![
+                ("binance".to_string(), "2".parse().unwrap()),
+                ("kraken".to_string(), "1".parse().unwrap()),
+                ("bitstamp".to_string(), "0.5".parse().unwrap()),
+            ]
+        );
+        assert_eq!(merged[1].exchange, "kraken");
+        assert_eq!(
+            merged[1].contributions,
+            vec![("kraken".to_string(), "4".parse().unwrap())]
+        );
+    }
+
+    #[test]
+    fn does_not_merge_levels_with_different_effective_prices() {
+        let mut binance_level = level("binance", "0.05", "2");
+        binance_level.effective_price = Some("0.04995".parse().unwrap());
+        let mut kraken_level = level("kraken", "0.05", "1");
+        kraken_level.effective_price = Some("0.04987".parse().unwrap());
+
+        let merged = merge_levels(vec![binance_level, kraken_level]);
+
+        assert_eq!(merged.len(), 2);
+        assert_eq!(merged[0].exchange, "binance");
+        assert_eq!(merged[1].exchange, "kraken");
+    }
+
+    #[test]
+    fn summary_joins_exchanges_of_merged_buckets() {
+        let mut binance = ExchangeOrderbookData::new(
+            "binance".to_string(),
+            price_amounts(&[("0.0501", "1"), ("0.0502", "1")]),
+            price_amounts(&[("0.0499", "2"), ("0.0489", "1")]),
+        );
+        binance.trim(2);
+        let kraken = ExchangeOrderbookData::new(
+            "kraken".to_string(),
+            price_amounts(&[("0.0505", "0.5")]),
+            price_amounts(&[("0.0495", "1"), ("0.0485", "3")]),
+        );
+
+        let summary = calculate_summary(
+            &pair_orderbooks(vec![binance, kraken]),
+            &view(true, "0.001"),
+            u64::MAX,
+        )
+        .unwrap();
+
+        let bids: Vec<_> = summary
+            .bids
+            .iter()
+            .map(|level| {
+                (
+                    level.exchange.as_str(),
+                    level.exact_price.as_str(),
+                    level.exact_amount.as_str(),
+                )
+            })
+            .collect();
+        let asks: Vec<_> = summary
+            .asks
+            .iter()
+            .map(|level| {
+                (
+                    level.exchange.as_str(),
+                    level.exact_price.as_str(),
+                    level.exact_amount.as_str(),
+                )
+            })
+            .collect();
+
+        // Binance bid bucket 0.048 is dropped, because its book continues beyond the data
+        assert_eq!(
+            bids,
+            vec![("binance,kraken", "0.049", "3"), ("kraken", "0.048", "3")]
+        );
+        assert_eq!(asks, vec![("binance,kraken", "0.051", "2.5")]);
+        assert_eq!(summary.exact_spread.as_deref(), Some("0.002"));
+    }
 }
@@ -0,0 +1,87 @@
+use std::{collections::HashMap, error::Error, sync::Arc};
+
+use crate::decimal::Decimal;
+use crate::helpers::get_env_var_or_default;
+
+/// Trading fees of an exchange, as fractions of trade value, like `0.001` for 0.1%
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FeeSchedule {
+    pub maker: Decimal,
+    pub taker: Decimal,
+}
+
+impl FeeSchedule {
+    /// What a taker gets for selling a unit at bid `price`, after fees
+    pub fn effective_bid_price(&self, price: Decimal) -> Decimal {
+        price - price * self.taker
+    }
+
+    /// What a taker pays for buying a unit at ask `price`, including fees
+    pub fn effective_ask_price(&self, price: Decimal) -> Decimal {
+        price + price * self.taker
+    }
+}
+
+/// Fee schedule of every exchange, keyed by exchange name
+pub type FeeSchedules = Arc<HashMap<&'static str, FeeSchedule>>;
+
+/// Reads `<EXCHANGE>_MAKER_FEE` and `<EXCHANGE>_TAKER_FEE` of every exchange, like `KRAKEN_TAKER_FEE=0.0026`.
+/// Fees which are not set are 0, and fees which are set but invalid are an error.
+pub fn get_fee_schedules(exchanges: &[&'static str]) -> Result<FeeSchedules, Box<dyn Error>> {
+    let mut fee_schedules = HashMap::new();
+
+    for exchange in exchanges {
+        let get_fee = |kind: &str| -> Result<Decimal, Box<dyn Error>> {
+            let var_name = format!("{}_{}_FEE", exchange.to_uppercase(), kind);
+            let value = get_env_var_or_default(&var_name, String::new());
+
+            parse_fee(&value).map_err(|error| format!("Invalid {}: {}", var_name, error).into())
+        };
+
+        let fee_schedule = FeeSchedule {
+            maker: get_fee("MAKER")?,
+            taker: get_fee("TAKER")?,
+        };
+        fee_schedules.insert(*exchange, fee_schedule);
+    }
+
+    Ok(Arc::new(fee_schedules))
+}
+
+/// Parses fee fraction, empty value is 0
+fn parse_fee(value: &str) -> Result<Decimal, String> {
+    let value = value.trim();
+    if value.is_empty() {
+        return Ok(Decimal::ZERO);
+    }
+
+    let fee: Decimal = value
+        .parse()
+        .map_err(|_| format!("`{}` is not a number", value))?;
+
+    if fee.is_negative() || fee >= Decimal::ONE {
+        return Err(format!("`{}` is not a fraction between 0 and 1", value));
+    }
+
+    Ok(fee)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_fee_fractions() {
+        assert_eq!(parse_fee(""), Ok(Decimal::ZERO));
+        assert_eq!(parse_fee("0"), Ok(Decimal::ZERO));
+        assert_eq!(parse_fee("0.0026"), Ok("0.0026".parse().unwrap()));
+    }
+
+    #[test]
+    fn rejects_invalid_fees() {
+        assert!(parse_fee("0.1%").is_err());
+        assert!(parse_fee("abc").is_err());
+        assert!(parse_fee("-0.001").is_err());
+        assert!(parse_fee("1").is_err());
+    }
+}
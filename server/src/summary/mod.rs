@@ -1,43 +1,96 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
-use crate::api::orderbook::Summary;
 use crate::data_sources::output_data_format::ExchangeOrderbookData;
+use crate::data_sources::status::ExchangeStatuses;
+use crate::decimal::Decimal;
 
 mod calculate;
-use calculate::calculate_summary;
+pub use calculate::calculate_summary;
 
-pub fn get_summary_rx(
+mod updates;
+pub use updates::BookUpdatesBuilder;
+
+mod fees;
+pub use fees::{get_fee_schedules, FeeSchedules};
+
+mod quote;
+pub use quote::{calculate_quote, QuoteSide};
+
+/// Latest orderbook data of every exchange of a pair
+#[derive(Debug, Clone)]
+pub struct PairOrderbooks {
+    /// Increases by 1 with every update
+    pub sequence: u64,
+    pub orderbooks: HashMap<String, ExchangeOrderbookData>,
+    /// Exchanges which are aggregated for the pair, reported in summaries even if they have no data
+    pub exchanges: Vec<&'static str>,
+    /// Connection states are read when summary is calculated, so they are always up to date
+    pub exchange_statuses: ExchangeStatuses,
+    pub fee_schedules: FeeSchedules,
+}
+
+/// Latest orderbooks of a pair, shared by all its clients
+pub type ExchangeOrderbooks = Arc<PairOrderbooks>;
+
+/// Which part of the aggregated orderbook a client wants to receive
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SummaryView {
+    pub depth: u16,
+    /// If not empty, only these exchanges are aggregated
+    pub include_exchanges: Vec<String>,
+    /// These exchanges are never aggregated
+    pub exclude_exchanges: Vec<String>,
+    /// Merge levels of different exchanges with the same price
+    pub merge_levels: bool,
+    /// Price bucket size, 0 means no grouping
+    pub tick_size: Decimal,
+    /// Rank levels by price after taker fees
+    pub fee_adjusted: bool,
+}
+
+impl SummaryView {
+    pub fn uses_exchange(&self, exchange: &str) -> bool {
+        let is_included = self.include_exchanges.is_empty()
+            || self.include_exchanges.iter().any(|name| name == exchange);
+        let is_excluded = self.exclude_exchanges.iter().any(|name| name == exchange);
+
+        is_included && !is_excluded
+    }
+}
+
+/// Collects orderbook data of all exchanges of a pair, and sends the latest state on every update.
+/// Summaries are calculated from it for every client separately.
+pub fn get_orderbooks_rx(
     data_rx: flume::Receiver<ExchangeOrderbookData>,
-    depth: u16,
-    data_lifetime_ms: u64,
-) -> flume::Receiver<Summary> {
-    let (tx, rx) = flume::bounded::<Summary>(10);
+    exchanges: Vec<&'static str>,
+    exchange_statuses: ExchangeStatuses,
+    fee_schedules: FeeSchedules,
+) -> flume::Receiver<ExchangeOrderbooks> {
+    let (tx, rx) = flume::bounded::<ExchangeOrderbooks>(10);
 
     tokio::spawn(async move {
-        let mut orderbook_data: HashMap<String, ExchangeOrderbookData> = HashMap::new();
+        let mut pair_orderbooks = PairOrderbooks {
+            sequence: 0,
+            orderbooks: HashMap::new(),
+            exchanges,
+            exchange_statuses,
+            fee_schedules,
+        };
 
-        while !data_rx.is_disconnected() {
-            let data_rx_drain = data_rx.drain();
-            let mut need_to_recalculate_summary = false;
+        // Wait for new data without blocking the runtime, then take everything that is already queued
+        while let Ok(data) = data_rx.recv_async().await {
+            let orderbooks = &mut pair_orderbooks.orderbooks;
+            orderbooks.insert(data.exchange.clone(), data);
 
-            for data in data_rx_drain {
-                orderbook_data.insert(data.exchange.clone(), data);
-                need_to_recalculate_summary = true;
+            for data in data_rx.drain() {
+                orderbooks.insert(data.exchange.clone(), data);
             }
 
-            if need_to_recalculate_summary {
-                let summary = calculate_summary(orderbook_data.clone(), depth, data_lifetime_ms);
-
-                if let Some(summary) = summary {
-                    // println!("Summary calculated. Spread: {}", summary.spread);
-                    tx.send_async(summary)
-                        .await
-                        .expect("Failed to send summary");
-                } else {
-                    // if all data is too old, or there is not enough data
-                    println!("Failed to calculate summary");
-                }
-            }
+            pair_orderbooks.sequence += 1;
+
+            tx.send_async(Arc::new(pair_orderbooks.clone()))
+                .await
+                .expect("Failed to send orderbooks");
         }
 
         println!("Summary thread finished");
@@ -0,0 +1,317 @@
+use crate::api::orderbook::{ExchangeAllocation, Quote};
+use crate::decimal::Decimal;
+use crate::helpers::get_current_timestamp_ms;
+
+use super::calculate::{aggregate_levels, AggregatedLevels};
+use super::{PairOrderbooks, SummaryView};
+
+const BASIS_POINTS: f64 = 10000.0;
+
+/// Side of a quoted trade: buying takes asks, selling takes bids
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum QuoteSide {
+    Buy,
+    Sell,
+}
+
+/// Quantity taken from a single exchange
+struct Allocation {
+    exchange: String,
+    quantity: Decimal,
+    /// Sum of price times quantity of all levels taken
+    notional: Decimal,
+}
+
+/// Takes levels of exchanges used by the view, best first, until `quantity` is filled.
+/// Returns `None` if there is no fresh data.
+pub fn calculate_quote(
+    pair_orderbooks: &PairOrderbooks,
+    view: &SummaryView,
+    side: QuoteSide,
+    quantity: Decimal,
+    data_lifetime_ms: u64,
+) -> Option<Quote> {
+    let current_timestamp = get_current_timestamp_ms();
+
+    let AggregatedLevels {
+        bids,
+        asks,
+        exchange_statuses,
+        ..
+    } = aggregate_levels(pair_orderbooks, view, data_lifetime_ms, current_timestamp);
+
+    if asks.is_empty() && bids.is_empty() {
+        return None;
+    }
+
+    // Levels can be ranked by effective price, so the best raw prices are not necessarily the first ones
+    let best_bid = bids.iter().map(|level| level.price).max();
+    let best_ask = asks.iter().map(|level| level.price).min();
+    let mid_price = best_bid
+        .zip(best_ask)
+        .map(|(best_bid, best_ask)| (best_bid + best_ask) / (Decimal::ONE + Decimal::ONE));
+
+    let levels = match side {
+        QuoteSide::Buy => asks,
+        QuoteSide::Sell => bids,
+    };
+
+    let mut allocations: Vec<Allocation> = Vec::new();
+    let mut remaining_quantity = quantity;
+    let mut notional = Decimal::ZERO;
+    let mut effective_notional = Decimal::ZERO;
+    let mut worst_price: Option<Decimal> = None;
+
+    for level in levels {
+        if remaining_quantity.is_zero() {
+            break;
+        }
+
+        let taken_quantity = level.amount.min(remaining_quantity);
+        // Every allocation must have some quantity, as its VWAP is divided by it
+        if !taken_quantity.is_positive() {
+            continue;
+        }
+
+        let level_notional = level.price * taken_quantity;
+        remaining_quantity -= taken_quantity;
+        notional += level_notional;
+        effective_notional += level.ranking_price() * taken_quantity;
+
+        worst_price = Some(match (worst_price, side) {
+            (None, _) => level.price,
+            (Some(worst_price), QuoteSide::Buy) => worst_price.max(level.price),
+            (Some(worst_price), QuoteSide::Sell) => worst_price.min(level.price),
+        });
+
+        match allocations
+            .iter_mut()
+            .find(|allocation| allocation.exchange == level.exchange)
+        {
+            Some(allocation) => {
+                allocation.quantity += taken_quantity;
+                allocation.notional += level_notional;
+            }
+            None => allocations.push(Allocation {
+                exchange: level.exchange,
+                quantity: taken_quantity,
+                notional: level_notional,
+            }),
+        }
+    }
+
+    let filled_quantity = quantity - remaining_quantity;
+    let vwap = if filled_quantity.is_zero() {
+        None
+    } else {
+        Some(notional / filled_quantity)
+    };
+    let effective_vwap = vwap
+        .filter(|_| view.fee_adjusted)
+        .map(|_| effective_notional / filled_quantity);
+
+    let slippage = vwap.zip(mid_price).map(|(vwap, mid_price)| match side {
+        QuoteSide::Buy => vwap - mid_price,
+        QuoteSide::Sell => mid_price - vwap,
+    });
+    let slippage_bps = slippage
+        .zip(mid_price)
+        .filter(|(_, mid_price)| mid_price.is_positive())
+        .map(|(slippage, mid_price)| (slippage / mid_price).to_f64() * BASIS_POINTS);
+
+    Some(Quote {
+        sequence: pair_orderbooks.sequence,
+        timestamp: current_timestamp,
+        allocations: allocations
+            .into_iter()
+            .map(|allocation| {
+                let vwap = allocation.notional / allocation.quantity;
+
+                ExchangeAllocation {
+                    exchange: allocation.exchange,
+                    quantity: allocation.quantity.to_f64(),
+                    exact_quantity: allocation.quantity.to_string(),
+                    vwap: vwap.to_f64(),
+                    exact_vwap: vwap.to_string(),
+                }
+            })
+            .collect(),
+        filled_quantity: filled_quantity.to_f64(),
+        exact_filled_quantity: filled_quantity.to_string(),
+        unfilled_quantity: remaining_quantity.to_f64(),
+        exact_unfilled_quantity: remaining_quantity.to_string(),
+        vwap: vwap.map(|vwap| vwap.to_f64()),
+        exact_vwap: vwap.map(|vwap| vwap.to_string()),
+        worst_price: worst_price.map(|price| price.to_f64()),
+        exact_worst_price: worst_price.map(|price| price.to_string()),
+        mid_price: mid_price.map(|price| price.to_f64()),
+        exact_mid_price: mid_price.map(|price| price.to_string()),
+        slippage: slippage.map(|slippage| slippage.to_f64()),
+        exact_slippage: slippage.map(|slippage| slippage.to_string()),
+        slippage_bps,
+        effective_vwap: effective_vwap.map(|vwap| vwap.to_f64()),
+        exact_effective_vwap: effective_vwap.map(|vwap| vwap.to_string()),
+        exchange_statuses,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+    use std::sync::{Arc, RwLock};
+
+    use crate::data_sources::output_data_format::ExchangeOrderbookData;
+    use crate::summary::fees::FeeSchedule;
+
+    use super::*;
+
+    fn price_amounts(levels: &[(&str, &str)]) -> Vec<(Decimal, Decimal)> {
+        levels
+            .iter()
+            .map(|(price, amount)| (price.parse().unwrap(), amount.parse().unwrap()))
+            .collect()
+    }
+
+    fn view(fee_adjusted: bool) -> SummaryView {
+        SummaryView {
+            depth: 10,
+            include_exchanges: Vec::new(),
+            exclude_exchanges: Vec::new(),
+            merge_levels: false,
+            tick_size: Decimal::ZERO,
+            fee_adjusted,
+        }
+    }
+
+    /// Binance asks 100 x 1 and 102 x 2, bids 99 x 1. Kraken asks 101 x 1, bids 98 x 1.
+    fn pair_orderbooks(kraken_taker_fee: &str) -> PairOrderbooks {
+        let binance = ExchangeOrderbookData::new(
+            "binance".to_string(),
+            price_amounts(&[("100", "1"), ("102", "2")]),
+            price_amounts(&[("99", "1")]),
+        );
+        let kraken = ExchangeOrderbookData::new(
+            "kraken".to_string(),
+            price_amounts(&[("101", "1")]),
+            price_amounts(&[("98", "1")]),
+        );
+        let kraken_fee_schedule = FeeSchedule {
+            maker: Decimal::ZERO,
+            taker: kraken_taker_fee.parse().unwrap(),
+        };
+
+        PairOrderbooks {
+            sequence: 1,
+            exchanges: vec!["binance", "kraken"],
+            orderbooks: [binance, kraken]
+                .into_iter()
+                .map(|orderbook| (orderbook.exchange.clone(), orderbook))
+                .collect(),
+            exchange_statuses: Arc::new(RwLock::new(HashMap::new())),
+            fee_schedules: Arc::new(HashMap::from([("kraken", kraken_fee_schedule)])),
+        }
+    }
+
+    fn quote(
+        pair_orderbooks: &PairOrderbooks,
+        fee_adjusted: bool,
+        side: QuoteSide,
+        quantity: &str,
+    ) -> Quote {
+        calculate_quote(
+            pair_orderbooks,
+            &view(fee_adjusted),
+            side,
+            quantity.parse().unwrap(),
+            u64::MAX,
+        )
+        .unwrap()
+    }
+
+    /// `exchange quantity vwap` of every allocation
+    fn format_allocations(quote: &Quote) -> Vec<String> {
+        quote
+            .allocations
+            .iter()
+            .map(|allocation| {
+                format!(
+                    "{} {} {}",
+                    allocation.exchange, allocation.exact_quantity, allocation.exact_vwap
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn buy_takes_asks_across_exchanges() {
+        let quote = quote(&pair_orderbooks("0"), false, QuoteSide::Buy, "3");
+
+        assert_eq!(
+            format_allocations(&quote),
+            vec!["binance 2 101", "kraken 1 101"]
+        );
+        assert_eq!(quote.exact_filled_quantity, "3");
+        assert_eq!(quote.exact_unfilled_quantity, "0");
+        assert_eq!(quote.exact_vwap.as_deref(), Some("101"));
+        assert_eq!(quote.exact_worst_price.as_deref(), Some("102"));
+        assert_eq!(quote.exact_mid_price.as_deref(), Some("99.5"));
+        assert_eq!(quote.exact_slippage.as_deref(), Some("1.5"));
+        assert!((quote.slippage_bps.unwrap() - 150.753768).abs() < 0.000001);
+        assert_eq!(quote.exact_effective_vwap, None);
+    }
+
+    #[test]
+    fn sell_takes_bids_across_exchanges() {
+        let quote = quote(&pair_orderbooks("0"), false, QuoteSide::Sell, "2");
+
+        assert_eq!(
+            format_allocations(&quote),
+            vec!["binance 1 99", "kraken 1 98"]
+        );
+        assert_eq!(quote.exact_vwap.as_deref(), Some("98.5"));
+        assert_eq!(quote.exact_worst_price.as_deref(), Some("98"));
+        assert_eq!(quote.exact_slippage.as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn reports_unfilled_quantity() {
+        let quote = quote(&pair_orderbooks("0"), false, QuoteSide::Buy, "5");
+
+        assert_eq!(quote.exact_filled_quantity, "4");
+        assert_eq!(quote.exact_unfilled_quantity, "1");
+        assert_eq!(quote.exact_vwap.as_deref(), Some("101.25"));
+        assert_eq!(quote.exact_worst_price.as_deref(), Some("102"));
+    }
+
+    #[test]
+    fn fee_adjusted_quote_takes_cheapest_levels_after_fees() {
+        let pair_orderbooks = pair_orderbooks("0.02");
+
+        // Kraken ask costs 103.02 with fee, more than Binance ask at 102
+        let quote_with_fees = quote(&pair_orderbooks, true, QuoteSide::Buy, "2");
+        let quote_without_fees = quote(&pair_orderbooks, false, QuoteSide::Buy, "2");
+
+        assert_eq!(format_allocations(&quote_with_fees), vec!["binance 2 101"]);
+        assert_eq!(quote_with_fees.exact_effective_vwap.as_deref(), Some("101"));
+        assert_eq!(
+            format_allocations(&quote_without_fees),
+            vec!["binance 1 100", "kraken 1 101"]
+        );
+    }
+
+    #[test]
+    fn returns_nothing_without_fresh_data() {
+        let mut pair_orderbooks = pair_orderbooks("0");
+        pair_orderbooks.orderbooks.clear();
+
+        let quote = calculate_quote(
+            &pair_orderbooks,
+            &view(false),
+            QuoteSide::Buy,
+            Decimal::ONE,
+            u64::MAX,
+        );
+
+        assert!(quote.is_none());
+    }
+}
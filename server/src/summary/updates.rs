@@ -0,0 +1,245 @@
+use std::collections::HashMap;
+
+use crate::api::orderbook::{BookUpdate, Level, LevelAction, LevelUpdate, Summary};
+
+/// Builds book updates of a single client stream.
+/// The first update is a snapshot, the next ones contain only levels which differ from the previous summary.
+#[derive(Default)]
+pub struct BookUpdatesBuilder {
+    previous: Option<Summary>,
+    sequence: u64,
+}
+
+impl BookUpdatesBuilder {
+    /// Returns `None` if no level is changed
+    pub fn build(&mut self, summary: Summary) -> Option<BookUpdate> {
+        let (snapshot, bids, asks) = match &self.previous {
+            Some(previous) => (
+                false,
+                diff_levels(&previous.bids, &summary.bids),
+                diff_levels(&previous.asks, &summary.asks),
+            ),
+            None => (
+                true,
+                diff_levels(&[], &summary.bids),
+                diff_levels(&[], &summary.asks),
+            ),
+        };
+
+        self.previous = Some(summary);
+
+        if !snapshot && bids.is_empty() && asks.is_empty() {
+            return None;
+        }
+
+        self.sequence += 1;
+
+        Some(BookUpdate {
+            sequence: self.sequence,
+            snapshot,
+            bids,
+            asks,
+        })
+    }
+}
+
+/// Deleted levels first, then inserted and updated ones in the order of `current`
+fn diff_levels(previous: &[Level], current: &[Level]) -> Vec<LevelUpdate> {
+    let level_key = |level: &Level| (level.exchange.clone(), level.exact_price.clone());
+
+    let previous_levels: HashMap<_, &Level> = previous
+        .iter()
+        .map(|level| (level_key(level), level))
+        .collect();
+    let current_keys: Vec<_> = current.iter().map(level_key).collect();
+
+    let mut updates: Vec<LevelUpdate> = previous
+        .iter()
+        .filter(|level| !current_keys.contains(&level_key(level)))
+        .map(|level| LevelUpdate {
+            action: LevelAction::Delete as i32,
+            level: Some(level.clone()),
+        })
+        .collect();
+
+    for level in current {
+        // Contributions of merged levels can change as well as amount
+        let action = match previous_levels.get(&level_key(level)) {
+            None => LevelAction::Insert,
+            Some(previous_level) if *previous_level != level => LevelAction::Update,
+            Some(_) => continue,
+        };
+
+        updates.push(LevelUpdate {
+            action: action as i32,
+            level: Some(level.clone()),
+        });
+    }
+
+    updates
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn level(exchange: &str, price: &str, amount: &str) -> Level {
+        Level {
+            exchange: exchange.to_string(),
+            price: price.parse().unwrap(),
+            amount: amount.parse().unwrap(),
+            exact_price: price.to_string(),
+            exact_amount: amount.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn summary(bids: Vec<Level>, asks: Vec<Level>) -> Summary {
+        Summary {
+            bids,
+            asks,
+            ..Default::default()
+        }
+    }
+
+    /// Action and `exchange price amount` of every update
+    fn format_updates(updates: &[LevelUpdate]) -> Vec<(LevelAction, String)> {
+        updates
+            .iter()
+            .map(|update| {
+                let level = update.level.as_ref().unwrap();
+                (
+                    LevelAction::from_i32(update.action).unwrap(),
+                    format!(
+                        "{} {} {}",
+                        level.exchange, level.exact_price, level.exact_amount
+                    ),
+                )
+            })
+            .collect()
+    }
+
+    /// Applies updates to levels by (exchange, price), like a client does
+    fn apply(book: &mut HashMap<(String, String), Level>, updates: &[LevelUpdate]) {
+        for update in updates {
+            let level = update.level.clone().unwrap();
+            let key = (level.exchange.clone(), level.exact_price.clone());
+
+            match LevelAction::from_i32(update.action).unwrap() {
+                LevelAction::Delete => {
+                    assert!(book.remove(&key).is_some(), "Deleted unknown level");
+                }
+                LevelAction::Insert => {
+                    assert!(book.insert(key, level).is_none(), "Inserted known level");
+                }
+                LevelAction::Update => {
+                    assert!(book.insert(key, level).is_some(), "Updated unknown level");
+                }
+            }
+        }
+    }
+
+    fn to_book(levels: &[Level]) -> HashMap<(String, String), Level> {
+        levels
+            .iter()
+            .map(|level| {
+                let key = (level.exchange.clone(), level.exact_price.clone());
+                (key, level.clone())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn starts_with_snapshot() {
+        let mut builder = BookUpdatesBuilder::default();
+
+        let update = builder
+            .build(summary(
+                vec![level("binance", "0.049", "1")],
+                vec![level("kraken", "0.05", "2")],
+            ))
+            .unwrap();
+
+        assert!(update.snapshot);
+        assert_eq!(update.sequence, 1);
+        assert_eq!(
+            format_updates(&update.bids),
+            [(LevelAction::Insert, "binance 0.049 1".to_string())]
+        );
+        assert_eq!(
+            format_updates(&update.asks),
+            [(LevelAction::Insert, "kraken 0.05 2".to_string())]
+        );
+    }
+
+    #[test]
+    fn diffs_levels_by_exchange_and_price() {
+        let previous = vec![
+            level("binance", "0.05", "1"),
+            level("kraken", "0.05", "2"),
+            level("bitstamp", "0.049", "1"),
+        ];
+        let current = vec![
+            level("binance", "0.05", "3"),
+            level("kraken", "0.05", "2"),
+            level("okx", "0.048", "1"),
+        ];
+
+        assert_eq!(
+            format_updates(&diff_levels(&previous, &current)),
+            [
+                (LevelAction::Delete, "bitstamp 0.049 1".to_string()),
+                (LevelAction::Update, "binance 0.05 3".to_string()),
+                (LevelAction::Insert, "okx 0.048 1".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn skips_unchanged_summaries() {
+        let mut builder = BookUpdatesBuilder::default();
+        let bids = vec![level("binance", "0.049", "1")];
+
+        builder.build(summary(bids.clone(), vec![])).unwrap();
+        assert!(builder.build(summary(bids, vec![])).is_none());
+
+        // Sequence has no gaps
+        let update = builder
+            .build(summary(vec![level("binance", "0.049", "2")], vec![]))
+            .unwrap();
+        assert!(!update.snapshot);
+        assert_eq!(update.sequence, 2);
+    }
+
+    #[test]
+    fn updates_reproduce_current_book() {
+        let books = [
+            vec![level("binance", "0.05", "1"), level("kraken", "0.051", "2")],
+            vec![
+                level("kraken", "0.049", "1"),
+                level("binance", "0.05", "1"),
+                level("kraken", "0.051", "3"),
+            ],
+            vec![],
+            vec![level("okx", "0.05", "1"), level("binance", "0.05", "2")],
+            vec![level("binance", "0.05", "2")],
+        ];
+
+        let mut builder = BookUpdatesBuilder::default();
+        let mut client_bids = HashMap::new();
+        let mut client_asks = HashMap::new();
+
+        for bids in books.iter() {
+            // Asks change in the opposite order
+            let asks: Vec<Level> = bids.iter().rev().cloned().collect();
+
+            if let Some(update) = builder.build(summary(bids.clone(), asks.clone())) {
+                apply(&mut client_bids, &update.bids);
+                apply(&mut client_asks, &update.asks);
+            }
+
+            assert_eq!(client_bids, to_book(bids));
+            assert_eq!(client_asks, to_book(&asks));
+        }
+    }
+}